@@ -0,0 +1,187 @@
+//! Removal of comments and string contents, for sharing a project's structure without its prose
+
+/// Comment and string syntax of a family of languages
+struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Quote characters that delimit string literals
+    quotes: &'static [char],
+    /// Whether `"""` and `'''` open strings that may span lines
+    triple_quotes: bool,
+    /// Whether ordinary string literals may span lines
+    multiline_strings: bool,
+}
+
+/// C, Java, JavaScript, Go and the like
+const C_LIKE: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+    triple_quotes: false,
+    multiline_strings: false,
+};
+/// Rust, whose `'` starts lifetimes as well as characters and is therefore left alone
+const RUST: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"'],
+    triple_quotes: false,
+    multiline_strings: true,
+};
+/// Python, with triple-quoted strings
+const PYTHON: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+    triple_quotes: true,
+    multiline_strings: false,
+};
+/// Shell, Ruby, YAML, TOML and others with `#` comments
+const HASH: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+    triple_quotes: false,
+    multiline_strings: true,
+};
+/// SQL and Lua
+const DASH_DASH: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    triple_quotes: false,
+    multiline_strings: true,
+};
+
+/// Syntax by file extension (lowercase, without the dot)
+const SYNTAX_BY_EXTENSION: &[(&str, &Syntax)] = &[
+    ("rs", &RUST),
+    ("c", &C_LIKE),
+    ("h", &C_LIKE),
+    ("cc", &C_LIKE),
+    ("cpp", &C_LIKE),
+    ("cxx", &C_LIKE),
+    ("hpp", &C_LIKE),
+    ("cs", &C_LIKE),
+    ("java", &C_LIKE),
+    ("kt", &C_LIKE),
+    ("kts", &C_LIKE),
+    ("scala", &C_LIKE),
+    ("swift", &C_LIKE),
+    ("go", &C_LIKE),
+    ("js", &C_LIKE),
+    ("mjs", &C_LIKE),
+    ("cjs", &C_LIKE),
+    ("jsx", &C_LIKE),
+    ("ts", &C_LIKE),
+    ("tsx", &C_LIKE),
+    ("dart", &C_LIKE),
+    ("php", &C_LIKE),
+    ("py", &PYTHON),
+    ("pyi", &PYTHON),
+    ("rb", &HASH),
+    ("sh", &HASH),
+    ("bash", &HASH),
+    ("zsh", &HASH),
+    ("r", &HASH),
+    ("pl", &HASH),
+    ("toml", &HASH),
+    ("yaml", &HASH),
+    ("yml", &HASH),
+    ("sql", &DASH_DASH),
+    ("lua", &DASH_DASH),
+];
+
+/// What replaces the contents of a non-empty string literal
+const STRING_PLACEHOLDER: &str = "<string>";
+
+/// `content` with comments removed and string literal contents replaced by a placeholder, or `None` when
+/// the file's language isn't supported.
+///
+/// This is a heuristic scanner rather than a parser: it knows each language's comment markers and quotes,
+/// but not constructs like raw strings, heredocs or regex literals, so it can miss or mangle some text.
+/// Lines left empty by a removed comment are dropped.
+pub(crate) fn anonymize(path: &str, content: &str) -> Option<String> {
+    let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+    let (_, syntax) = SYNTAX_BY_EXTENSION.iter().find(|(known, _)| *known == extension)?;
+
+    let mut lines: Vec<(String, bool)> = vec![(String::new(), false)];
+    let mut rest = content;
+    while let Some(c) = rest.chars().next() {
+        let (text, had_comment) = lines.last_mut().expect("there is always a current line");
+
+        if syntax.line_comments.iter().any(|marker| rest.starts_with(marker)) {
+            rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+            *had_comment = true;
+            continue;
+        }
+
+        if let Some((open, close)) = syntax.block_comment.filter(|(open, _)| rest.starts_with(open)) {
+            let end = rest[open.len()..].find(close).map_or(rest.len(), |end| open.len() + end + close.len());
+            *had_comment = true;
+            // Keep the line breaks so the following code stays on its own lines
+            for _ in rest[..end].matches('\n') {
+                lines.push((String::new(), true));
+            }
+            rest = &rest[end..];
+            continue;
+        }
+
+        let triple = syntax.triple_quotes && rest.starts_with(&c.to_string().repeat(3));
+        let delimiter = if triple { c.to_string().repeat(3) } else { c.to_string() };
+        let multiline = triple || syntax.multiline_strings || c == '`';
+        // An unclosed quote is taken literally, e.g. an apostrophe in a language without character literals
+        let closing = syntax
+            .quotes
+            .contains(&c)
+            .then(|| string_end(&rest[delimiter.len()..], &delimiter, multiline))
+            .flatten();
+        if let Some(closing) = closing {
+            let end = delimiter.len() + closing;
+            let inner_is_empty = end == delimiter.len() * 2;
+
+            text.push_str(&delimiter);
+            if !inner_is_empty {
+                text.push_str(STRING_PLACEHOLDER);
+            }
+            text.push_str(&delimiter);
+            let newlines = rest[..end].matches('\n').count();
+            for _ in 0..newlines {
+                lines.push((String::new(), true));
+            }
+            rest = &rest[end..];
+            continue;
+        }
+
+        if c == '\n' {
+            lines.push((String::new(), false));
+        } else {
+            text.push(c);
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    let kept: Vec<String> = lines
+        .into_iter()
+        .filter(|(text, had_comment)| !(*had_comment && text.trim().is_empty()))
+        .map(|(text, had_comment)| if had_comment { text.trim_end().to_string() } else { text })
+        .collect();
+    Some(kept.join("\n"))
+}
+
+/// Byte offset just past the closing `delimiter` of a string whose contents start at `after_open`, or
+/// `None` when it isn't closed (on the same line, unless strings may span lines). Backslashes escape the
+/// next character.
+fn string_end(after_open: &str, delimiter: &str, multiline: bool) -> Option<usize> {
+    let mut chars = after_open.char_indices();
+    while let Some((index, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if after_open[index..].starts_with(delimiter) {
+            return Some(index + delimiter.len());
+        } else if c == '\n' && !multiline {
+            return None;
+        }
+    }
+    None
+}
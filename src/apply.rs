@@ -0,0 +1,141 @@
+//! Writing the files of an (edited) context file back to disk
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use anyhow::{Context, Result};
+
+use crate::extract_files;
+use crate::parse::read_error_paths;
+use crate::{READ_ERROR_PREFIX, TRUNCATION_NOTE};
+
+/// Options for [`apply_context`]
+#[derive(Debug, Clone, Default)]
+pub struct ApplyOptions {
+    /// Report what would change without writing anything
+    pub dry_run: bool,
+    /// Keep a copy of each overwritten file as `<name>.bak`
+    pub backup: bool,
+    /// Allow paths that resolve outside the root directory
+    pub allow_outside_root: bool,
+}
+
+/// What happened (or, in a dry run, would happen) to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyAction {
+    Created,
+    Updated,
+    Unchanged,
+    /// The block holds a read-error or size-limit placeholder, is left empty because the file couldn't be
+    /// read, or holds only some lines of the file (a line excerpt or a truncated file)
+    Skipped,
+}
+
+/// A file block of the context and what was done with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedFile {
+    pub path: String,
+    pub action: ApplyAction,
+}
+
+/// Write every file block of a context file to its path under `root`.
+///
+/// All paths are checked before anything is written, so a context with a path outside `root`
+/// is rejected as a whole unless `allow_outside_root` is set.
+pub fn apply_context(input: &str, root: &Path, options: &ApplyOptions) -> Result<Vec<AppliedFile>> {
+    let files = extract_files(input)?;
+    let read_errors = read_error_paths(input);
+
+    let mut planned = Vec::new();
+    for (path, content) in files {
+        let target = resolve_target(root, &path, options.allow_outside_root)?;
+        planned.push((path, target, content));
+    }
+
+    let mut applied = Vec::new();
+    for (path, target, content) in planned {
+        let unread = content.is_empty() && read_errors.contains(&path);
+        let action = if unread || is_placeholder(&content) || is_line_excerpt(&content) || is_truncated(&content) {
+            ApplyAction::Skipped
+        } else {
+            match fs::read(&target) {
+                Ok(existing) if existing == content.as_bytes() => ApplyAction::Unchanged,
+                Ok(_) => ApplyAction::Updated,
+                Err(_) => ApplyAction::Created,
+            }
+        };
+
+        if !options.dry_run && matches!(action, ApplyAction::Created | ApplyAction::Updated) {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .context(format!("Failed to create directory: {}", parent.display()))?;
+            }
+            if options.backup && action == ApplyAction::Updated {
+                let mut backup = target.clone().into_os_string();
+                backup.push(".bak");
+                fs::copy(&target, &backup).context(format!("Failed to back up {}", target.display()))?;
+            }
+            fs::write(&target, &content).context(format!("Failed to write {}", target.display()))?;
+        }
+
+        applied.push(AppliedFile { path, action });
+    }
+
+    Ok(applied)
+}
+
+/// Resolve a path from the context against `root`, refusing paths that escape it
+fn resolve_target(root: &Path, path: &str, allow_outside_root: bool) -> Result<PathBuf> {
+    let target = normalize(&root.join(path));
+    if allow_outside_root {
+        return Ok(target);
+    }
+
+    let root = normalize(root);
+    let escapes = !target.starts_with(&root) || {
+        // A symlinked directory inside the root may still point elsewhere
+        let canonical_root = root.canonicalize().unwrap_or_else(|_| root.clone());
+        let existing_ancestor = target.ancestors().find(|ancestor| ancestor.exists());
+        existing_ancestor
+            .and_then(|ancestor| ancestor.canonicalize().ok())
+            .is_some_and(|ancestor| !ancestor.starts_with(&canonical_root))
+    };
+    if escapes {
+        anyhow::bail!(
+            "Refusing to write {}: it is outside {} (use --allow-outside-cwd to permit this)",
+            path,
+            root.display()
+        );
+    }
+
+    Ok(target)
+}
+
+/// Resolve `.` and `..` components without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Whether a block's content holds line ranges of a file (`--add-file path:start-end`) rather than all of it
+fn is_line_excerpt(content: &str) -> bool {
+    content.lines().next().is_some_and(|line| line.starts_with("[lines ") && line.contains(" of ") && line.ends_with(']'))
+}
+
+/// Whether a block's content had lines cut out of its middle to fit a token limit
+fn is_truncated(content: &str) -> bool {
+    content.lines().any(|line| line.starts_with("... [") && line.ends_with("] ...") && line.contains(TRUNCATION_NOTE))
+}
+
+/// Whether a block's content is the message embedded for a file that could not be included
+fn is_placeholder(content: &str) -> bool {
+    !content.contains('\n') && ((content.starts_with(READ_ERROR_PREFIX) && content.ends_with(']')) || content.starts_with("File skipped: "))
+}
@@ -0,0 +1,107 @@
+//! Project configuration loaded from `contextify.toml`, with optional named profiles
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::OutputFormat;
+
+/// Name of the project configuration file looked up in the current directory
+pub const CONFIG_FILE_NAME: &str = "contextify.toml";
+
+/// Settings that can be given at the top level of the config file or inside a profile
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigSettings {
+    /// Specific files or directories to process
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_paths: Option<Vec<PathBuf>>,
+    /// Additional blacklist patterns
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blacklist_patterns: Vec<String>,
+    /// Additional whitelist patterns
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub whitelist_patterns: Vec<String>,
+    /// Blacklist file path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blacklist_file: Option<String>,
+    /// Whitelist file path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub whitelist_file: Option<String>,
+    /// Output file path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Output format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+}
+
+impl ConfigSettings {
+    /// Layer `other` on top of these settings: scalar values in `other` win, pattern lists are appended
+    pub fn merge(&mut self, other: &ConfigSettings) {
+        if other.input_paths.is_some() {
+            self.input_paths = other.input_paths.clone();
+        }
+        self.blacklist_patterns.extend(other.blacklist_patterns.iter().cloned());
+        self.whitelist_patterns.extend(other.whitelist_patterns.iter().cloned());
+        if other.blacklist_file.is_some() {
+            self.blacklist_file = other.blacklist_file.clone();
+        }
+        if other.whitelist_file.is_some() {
+            self.whitelist_file = other.whitelist_file.clone();
+        }
+        if other.output.is_some() {
+            self.output = other.output.clone();
+        }
+        if other.format.is_some() {
+            self.format = other.format;
+        }
+    }
+}
+
+/// The contents of a `contextify.toml` file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Settings that apply to every run
+    #[serde(flatten)]
+    pub settings: ConfigSettings,
+    /// Named sets of settings selected with `--profile`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ConfigSettings>,
+}
+
+impl Config {
+    /// Parse a config file
+    pub fn load(path: &Path) -> Result<Config> {
+        let content = fs::read_to_string(path)
+            .context(format!("Failed to read config file: {}", path.display()))?;
+        toml::from_str(&content).context(format!("Failed to parse config file: {}", path.display()))
+    }
+
+    /// The config as the contents of a `contextify.toml` file
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string_pretty(self).context("Failed to serialize the config")
+    }
+
+    /// The effective settings: the top-level settings with the selected profile layered on top
+    pub fn resolve(&self, profile: Option<&str>) -> Result<ConfigSettings> {
+        let mut settings = self.settings.clone();
+
+        if let Some(name) = profile {
+            let Some(profile_settings) = self.profiles.get(name) else {
+                let available: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                anyhow::bail!(
+                    "Unknown profile '{}' (available profiles: {})",
+                    name,
+                    if available.is_empty() { String::from("none") } else { available.join(", ") }
+                );
+            };
+            settings.merge(profile_settings);
+        }
+
+        Ok(settings)
+    }
+}
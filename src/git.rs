@@ -0,0 +1,55 @@
+//! Queries of the git repository a project lives in, through the `git` command
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+/// The most recent tag reachable from `HEAD` (`git describe --tags --abbrev=0`)
+pub fn latest_tag(dir: &Path) -> Result<String> {
+    match git(dir, &["describe", "--tags", "--abbrev=0"]) {
+        Ok(tag) => Ok(tag.trim().to_string()),
+        Err(e) if format!("{:#}", e).contains("No names found") => {
+            anyhow::bail!("No git tags found in {}; tag a release first or name a revision instead", dir.display())
+        }
+        Err(e) => Err(e).context("Failed to find the latest git tag"),
+    }
+}
+
+/// Top-level directory of the working tree `dir` is in (`git rev-parse --show-toplevel`)
+pub fn repository_root(dir: &Path) -> Result<PathBuf> {
+    Ok(PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?.trim()))
+}
+
+/// Name of the checked-out branch, or `HEAD` when it is detached
+pub fn current_branch(dir: &Path) -> Result<String> {
+    Ok(git(dir, &["rev-parse", "--abbrev-ref", "HEAD"])?.trim().to_string())
+}
+
+/// Abbreviated hash of the checked-out commit
+pub fn head_commit(dir: &Path) -> Result<String> {
+    Ok(git(dir, &["rev-parse", "--short", "HEAD"])?.trim().to_string())
+}
+
+/// Paths, relative to `dir` and with `/` separators, of the files under `dir` that differ from `revision`
+/// in the working tree, plus untracked files that aren't ignored
+pub fn changed_files_since(dir: &Path, revision: &str) -> Result<HashSet<String>> {
+    let changed = git(dir, &["diff", "--name-only", "--relative", revision, "--"])
+        .with_context(|| format!("Failed to list the files changed since {}", revision))?;
+    let untracked = git(dir, &["ls-files", "--others", "--exclude-standard"]).context("Failed to list untracked files")?;
+    Ok(changed.lines().chain(untracked.lines()).filter(|line| !line.is_empty()).map(str::to_string).collect())
+}
+
+/// Run git in `dir` and return its standard output, or an error with its standard error
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git; is it installed?")?;
+    if !output.status.success() {
+        anyhow::bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,121 @@
+//! Self-contained HTML page for browsing a context: a collapsible file tree beside syntax-highlighted contents
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use anyhow::Result;
+use syntect::highlighting::ThemeSet;
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::SyntaxSet;
+
+use crate::output::{file_language, header_labels, structure_suffix};
+use crate::tree::TreeNode;
+use crate::{format_utc_timestamp, generation_timestamp, FileEntry, ProcessingOptions};
+
+/// Highlighting theme from syntect's bundled themes
+const THEME: &str = "InspiredGitHub";
+
+/// Page layout: a fixed sidebar with the tree and a scrolling pane with the files
+const STYLE: &str = "\
+body { margin: 0; display: flex; height: 100vh; font-family: system-ui, sans-serif; }
+nav { width: 22rem; flex-shrink: 0; overflow: auto; padding: 1rem; border-right: 1px solid #ddd; background: #f8f8f8; }
+nav ul { list-style: none; margin: 0; padding-left: 1rem; }
+nav > ul { padding-left: 0; }
+nav summary { cursor: pointer; }
+nav a { text-decoration: none; }
+main { flex-grow: 1; overflow: auto; padding: 1rem 2rem; }
+h1 { font-size: 1.1rem; }
+h2 { font-size: 1rem; font-family: monospace; border-bottom: 1px solid #ddd; padding-bottom: 0.25rem; }
+pre { padding: 0.75rem; overflow-x: auto; font-size: 0.85rem; }
+.note { color: #888; }
+";
+
+/// Write the HTML page. Files with content get a highlighted pane and are linked from the tree;
+/// skipped files and symlinks appear in the tree with their note.
+pub(crate) fn write_html(
+    writer: &mut dyn Write,
+    entries: &[FileEntry],
+    omitted_per_dir: &BTreeMap<String, usize>,
+    options: &ProcessingOptions,
+) -> Result<()> {
+    let with_content: Vec<&FileEntry> = if options.structure_only {
+        Vec::new()
+    } else {
+        entries.iter().filter(|entry| entry.has_content()).collect()
+    };
+    // Keyed by the path as the tree spells it, i.e. without empty components
+    let anchors: HashMap<String, usize> = with_content
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (tree_path(&entry.path), index))
+        .collect();
+
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>Project context</title>\n<style>\n{}</style>\n</head>\n<body>", STYLE)?;
+
+    let root = TreeNode::from_paths(
+        entries.iter().map(|entry| (entry.path.as_str(), structure_suffix(entry))),
+        omitted_per_dir,
+    );
+    let mut tree = String::new();
+    render_tree_html(&root, "", &anchors, &mut tree);
+    writeln!(writer, "<nav>\n<h1>Project Structure</h1>\n{}</nav>", tree)?;
+
+    writeln!(writer, "<main>")?;
+    if let Some(timestamp) = generation_timestamp(options.no_timestamps) {
+        writeln!(writer, "<p class=\"note\">Generated at {}</p>", format_utc_timestamp(timestamp))?;
+    }
+
+    let syntaxes = SyntaxSet::load_defaults_newlines();
+    let themes = ThemeSet::load_defaults();
+    let theme = &themes.themes[THEME];
+    for (index, entry) in with_content.iter().enumerate() {
+        let syntax = syntaxes
+            .find_syntax_by_extension(file_language(&entry.path))
+            .or_else(|| syntaxes.find_syntax_by_first_line(&entry.content))
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+        let highlighted = highlighted_html_for_string(&entry.content, &syntaxes, syntax, theme)?;
+
+        writeln!(writer, "<section id=\"file-{}\">", index)?;
+        writeln!(writer, "<h2>{}{}</h2>\n{}</section>", escape_html(&entry.path), header_labels(entry), highlighted)?;
+    }
+    writeln!(writer, "</main>\n</body>\n</html>")?;
+
+    Ok(())
+}
+
+/// Nested lists for the tree, with directories as open `<details>` elements and files linking to their pane
+fn render_tree_html(node: &TreeNode, prefix: &str, anchors: &HashMap<String, usize>, html: &mut String) {
+    html.push_str("<ul>\n");
+    for (name, child) in &node.children {
+        let path = if prefix.is_empty() { name.clone() } else { format!("{}/{}", prefix, name) };
+
+        if child.is_dir() {
+            html.push_str(&format!("<li><details open><summary>{}/</summary>\n", escape_html(name)));
+            render_tree_html(child, &path, anchors, html);
+            html.push_str("</details></li>\n");
+        } else if let Some(index) = anchors.get(&path) {
+            html.push_str(&format!("<li><a href=\"#file-{}\">{}</a></li>\n", index, escape_html(name)));
+        } else {
+            let note = child.note.as_deref().unwrap_or_default();
+            html.push_str(&format!("<li>{}<span class=\"note\">{}</span></li>\n", escape_html(name), escape_html(note)));
+        }
+    }
+    if node.omitted > 0 {
+        html.push_str(&format!("<li class=\"note\">({} more files in this directory omitted)</li>\n", node.omitted));
+    }
+    html.push_str("</ul>\n");
+}
+
+/// A display path joined from its non-empty components, as the tree builds it
+fn tree_path(path: &str) -> String {
+    path.split('/').filter(|component| !component.is_empty()).collect::<Vec<_>>().join("/")
+}
+
+/// Escape text for use in HTML content and attribute values
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
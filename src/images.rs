@@ -0,0 +1,16 @@
+//! Image descriptions for `--describe-images`, read from the file headers without decoding the pixels
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::ImageReader;
+
+/// Format and dimensions of an image, e.g. `PNG 512x512`
+pub(crate) fn describe_image(path: &Path) -> Result<String> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let format = reader.format().context("unrecognized image format")?;
+    let (width, height) = reader.into_dimensions().context("unreadable image header")?;
+    // `Png` and `Jpeg` rather than their extensions, which are `jpg` before `jpeg`
+    let name = format!("{:?}", format).to_uppercase();
+    Ok(format!("{} {}x{}", name, width, height))
+}
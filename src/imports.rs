@@ -0,0 +1,238 @@
+//! Intra-project import graph of Rust and JavaScript/TypeScript files, for ordering files leaves first
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// Extensions of JavaScript and TypeScript modules, in the order they are tried when resolving a specifier
+const SCRIPT_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+
+/// Whether imports are extracted from a file with this path
+pub(crate) fn has_imports(path: &str) -> bool {
+    path.ends_with(".rs") || SCRIPT_EXTENSIONS.iter().any(|extension| path.ends_with(&format!(".{}", extension)))
+}
+
+/// An order of `files` (display path and source) in which each file comes after the files it imports.
+///
+/// Only imports that resolve to another of the files count: `mod` and `use` (`crate::`, `self::`,
+/// `super::` and child modules) in Rust, relative `import`, `export ... from` and `require` specifiers in
+/// JavaScript and TypeScript. Files that are free to go next are taken by path, so files outside the graph
+/// stay in alphabetical order. A cycle is broken by taking the file with the fewest imports still pending,
+/// then by path.
+pub(crate) fn dependency_order(files: &[(&str, String)]) -> Vec<usize> {
+    let known: HashSet<&str> = files.iter().map(|(path, _)| *path).collect();
+    let index_of: HashMap<&str, usize> = files.iter().enumerate().map(|(index, (path, _))| (*path, index)).collect();
+
+    let mut pending = vec![0usize; files.len()];
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); files.len()];
+    for (index, (path, source)) in files.iter().enumerate() {
+        let imported: BTreeSet<usize> = imports_of(path, source, &known)
+            .iter()
+            .filter_map(|import| index_of.get(import.as_str()).copied())
+            .filter(|&imported| imported != index)
+            .collect();
+        pending[index] = imported.len();
+        for imported in imported {
+            dependents[imported].push(index);
+        }
+    }
+
+    let mut ready: BTreeSet<(&str, usize)> =
+        (0..files.len()).filter(|&index| pending[index] == 0).map(|index| (files[index].0, index)).collect();
+    let mut placed = vec![false; files.len()];
+    let mut order = Vec::with_capacity(files.len());
+    while order.len() < files.len() {
+        let next = match ready.pop_first() {
+            Some((_, index)) => index,
+            None => (0..files.len())
+                .filter(|&index| !placed[index])
+                .min_by_key(|&index| (pending[index], files[index].0))
+                .expect("an unplaced file remains"),
+        };
+        placed[next] = true;
+        order.push(next);
+        for &dependent in &dependents[next] {
+            pending[dependent] -= 1;
+            if pending[dependent] == 0 && !placed[dependent] {
+                ready.insert((files[dependent].0, dependent));
+            }
+        }
+    }
+    order
+}
+
+/// Display paths of the known files that the file at `path` imports
+fn imports_of(path: &str, source: &str, known: &HashSet<&str>) -> Vec<String> {
+    if path.ends_with(".rs") {
+        rust_imports(path, source, known)
+    } else if has_imports(path) {
+        script_imports(path, source, known)
+    } else {
+        Vec::new()
+    }
+}
+
+fn rust_imports(path: &str, source: &str, known: &HashSet<&str>) -> Vec<String> {
+    static MOD: OnceLock<Regex> = OnceLock::new();
+    static USE: OnceLock<Regex> = OnceLock::new();
+    static ALIAS: OnceLock<Regex> = OnceLock::new();
+    let mod_declaration = MOD.get_or_init(|| {
+        Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+([A-Za-z_][A-Za-z0-9_]*)\s*;").expect("valid regex")
+    });
+    let use_declaration =
+        USE.get_or_init(|| Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?use\s+([^;]+);").expect("valid regex"));
+    let alias = ALIAS.get_or_init(|| Regex::new(r"\s+as\s+[A-Za-z0-9_]+").expect("valid regex"));
+
+    let module_dir = rust_module_dir(path);
+    let mut imports = Vec::new();
+
+    for captures in mod_declaration.captures_iter(source) {
+        imports.extend(rust_module_file(&join(&module_dir, &captures[1]), known));
+    }
+
+    for captures in use_declaration.captures_iter(source) {
+        let tree: String = alias.replace_all(&captures[1], "").split_whitespace().collect();
+        for use_path in expand_use_tree(&tree) {
+            let segments: Vec<&str> = use_path.trim_start_matches("::").split("::").collect();
+            let (base, rest) = match segments[0] {
+                "crate" => match rust_crate_root(path, known) {
+                    Some(root) => (root, &segments[1..]),
+                    None => continue,
+                },
+                "self" => (module_dir.clone(), &segments[1..]),
+                "super" => {
+                    let supers = segments.iter().take_while(|segment| **segment == "super").count();
+                    let mut base = module_dir.clone();
+                    for _ in 0..supers {
+                        base = parent(&base).to_string();
+                    }
+                    (base, &segments[supers..])
+                }
+                _ => (module_dir.clone(), &segments[..]),
+            };
+
+            // The longest prefix that names a module file, or else the module the path starts from
+            let resolved = (1..=rest.len())
+                .rev()
+                .find_map(|length| rust_module_file(&join(&base, &rest[..length].join("/")), known))
+                .or_else(|| (segments[0] != "self" && base != module_dir).then(|| rust_module_file(&base, known)).flatten());
+            imports.extend(resolved);
+        }
+    }
+
+    imports
+}
+
+/// Expand a `use` tree without whitespace, e.g. `a::{b,c::{d,e}}`, into its paths
+fn expand_use_tree(tree: &str) -> Vec<String> {
+    let Some(open) = tree.find('{') else {
+        return vec![tree.to_string()];
+    };
+    let prefix = &tree[..open];
+    let inner = tree[open + 1..].strip_suffix('}').unwrap_or(&tree[open + 1..]);
+
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in inner.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&inner[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&inner[start..]);
+
+    parts
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .flat_map(expand_use_tree)
+        .map(|path| if path == "self" { prefix.trim_end_matches("::").to_string() } else { format!("{}{}", prefix, path) })
+        .collect()
+}
+
+/// The directory holding a Rust file's child modules: its own directory for `lib.rs`, `main.rs` and
+/// `mod.rs`, otherwise a directory named after the file
+fn rust_module_dir(path: &str) -> String {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    match name {
+        "lib.rs" | "main.rs" | "mod.rs" => dir.to_string(),
+        _ => join(dir, name.trim_end_matches(".rs")),
+    }
+}
+
+/// The file of the module whose child modules live in `module_dir`
+fn rust_module_file(module_dir: &str, known: &HashSet<&str>) -> Option<String> {
+    [format!("{}.rs", module_dir), join(module_dir, "mod.rs"), join(module_dir, "lib.rs"), join(module_dir, "main.rs")]
+        .into_iter()
+        .find(|candidate| known.contains(candidate.as_str()))
+}
+
+/// The directory of the crate root (`lib.rs` or `main.rs`) nearest above a Rust file
+fn rust_crate_root(path: &str, known: &HashSet<&str>) -> Option<String> {
+    let mut dir = parent(path);
+    loop {
+        if known.contains(join(dir, "lib.rs").as_str()) || known.contains(join(dir, "main.rs").as_str()) {
+            return Some(dir.to_string());
+        }
+        if dir.is_empty() {
+            return None;
+        }
+        dir = parent(dir);
+    }
+}
+
+fn script_imports(path: &str, source: &str, known: &HashSet<&str>) -> Vec<String> {
+    static SPECIFIER: OnceLock<Regex> = OnceLock::new();
+    let specifier = SPECIFIER.get_or_init(|| {
+        Regex::new(r#"(?:\bfrom|\bimport|\brequire\s*\()\s*\(?\s*['"](\.\.?/[^'"]*)['"]"#).expect("valid regex")
+    });
+
+    specifier
+        .captures_iter(source)
+        .filter_map(|captures| {
+            let target = resolve_relative(parent(path), &captures[1])?;
+            let stem = target.rsplit_once('.').filter(|(_, extension)| !extension.contains('/')).map(|(stem, _)| stem);
+            // `./x.js` may name `x.ts` in TypeScript
+            std::iter::once(target.clone())
+                .chain(stem.into_iter().flat_map(|stem| SCRIPT_EXTENSIONS.iter().map(move |extension| format!("{}.{}", stem, extension))))
+                .chain(SCRIPT_EXTENSIONS.iter().map(|extension| format!("{}.{}", target, extension)))
+                .chain(SCRIPT_EXTENSIONS.iter().map(|extension| format!("{}/index.{}", target, extension)))
+                .find(|candidate| known.contains(candidate.as_str()))
+        })
+        .collect()
+}
+
+/// `relative` (starting with `./` or `../`) resolved against the display directory `dir`
+fn resolve_relative(dir: &str, relative: &str) -> Option<String> {
+    let mut components: Vec<&str> = dir.split('/').filter(|component| !component.is_empty()).collect();
+    for component in relative.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            _ => components.push(component),
+        }
+    }
+    let joined = components.join("/");
+    Some(if dir.starts_with('/') { format!("/{}", joined) } else { joined })
+}
+
+/// The directory part of a display path (empty at the top level)
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
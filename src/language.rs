@@ -0,0 +1,151 @@
+//! Detection of a file's language from its name, for the summary header
+
+/// Languages by file extension (lowercase, without the dot)
+const LANGUAGES_BY_EXTENSION: &[(&str, &str)] = &[
+    ("rs", "Rust"),
+    ("py", "Python"),
+    ("pyi", "Python"),
+    ("ipynb", "Jupyter Notebook"),
+    ("js", "JavaScript"),
+    ("mjs", "JavaScript"),
+    ("cjs", "JavaScript"),
+    ("jsx", "JavaScript"),
+    ("ts", "TypeScript"),
+    ("tsx", "TypeScript"),
+    ("go", "Go"),
+    ("java", "Java"),
+    ("kt", "Kotlin"),
+    ("kts", "Kotlin"),
+    ("scala", "Scala"),
+    ("swift", "Swift"),
+    ("c", "C"),
+    ("h", "C"),
+    ("cc", "C++"),
+    ("cpp", "C++"),
+    ("cxx", "C++"),
+    ("hpp", "C++"),
+    ("hh", "C++"),
+    ("cs", "C#"),
+    ("rb", "Ruby"),
+    ("php", "PHP"),
+    ("lua", "Lua"),
+    ("dart", "Dart"),
+    ("ex", "Elixir"),
+    ("exs", "Elixir"),
+    ("erl", "Erlang"),
+    ("hs", "Haskell"),
+    ("ml", "OCaml"),
+    ("clj", "Clojure"),
+    ("r", "R"),
+    ("jl", "Julia"),
+    ("zig", "Zig"),
+    ("sh", "Shell"),
+    ("bash", "Shell"),
+    ("zsh", "Shell"),
+    ("fish", "Shell"),
+    ("ps1", "PowerShell"),
+    ("sql", "SQL"),
+    ("html", "HTML"),
+    ("htm", "HTML"),
+    ("css", "CSS"),
+    ("scss", "SCSS"),
+    ("sass", "Sass"),
+    ("less", "Less"),
+    ("vue", "Vue"),
+    ("svelte", "Svelte"),
+    ("md", "Markdown"),
+    ("markdown", "Markdown"),
+    ("rst", "reStructuredText"),
+    ("txt", "Text"),
+    ("json", "JSON"),
+    ("toml", "TOML"),
+    ("yaml", "YAML"),
+    ("yml", "YAML"),
+    ("xml", "XML"),
+    ("svg", "SVG"),
+    ("proto", "Protocol Buffers"),
+    ("graphql", "GraphQL"),
+    ("tf", "Terraform"),
+    ("nix", "Nix"),
+];
+
+/// Languages of files recognized by their whole name
+const LANGUAGES_BY_FILE_NAME: &[(&str, &str)] = &[
+    ("Dockerfile", "Dockerfile"),
+    ("Makefile", "Makefile"),
+    ("CMakeLists.txt", "CMake"),
+    ("Cargo.lock", "TOML"),
+];
+
+/// The language of a file judged by its name, or `Other` when it isn't recognized
+pub(crate) fn language_name(path: &str) -> &'static str {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    if let Some((_, language)) = LANGUAGES_BY_FILE_NAME.iter().find(|(name, _)| *name == file_name) {
+        return language;
+    }
+
+    let Some((_, extension)) = file_name.rsplit_once('.') else {
+        return "Other";
+    };
+    LANGUAGES_BY_EXTENSION
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map_or("Other", |(_, language)| language)
+}
+
+/// Line comment markers by language, for the languages that have them
+const LINE_COMMENTS: &[(&str, &str)] = &[
+    ("Rust", "//"),
+    ("JavaScript", "//"),
+    ("TypeScript", "//"),
+    ("Go", "//"),
+    ("Java", "//"),
+    ("Kotlin", "//"),
+    ("Scala", "//"),
+    ("Swift", "//"),
+    ("C", "//"),
+    ("C++", "//"),
+    ("C#", "//"),
+    ("PHP", "//"),
+    ("Dart", "//"),
+    ("Zig", "//"),
+    ("SCSS", "//"),
+    ("Sass", "//"),
+    ("Less", "//"),
+    ("Protocol Buffers", "//"),
+    ("Python", "#"),
+    ("Ruby", "#"),
+    ("Elixir", "#"),
+    ("R", "#"),
+    ("Julia", "#"),
+    ("Shell", "#"),
+    ("PowerShell", "#"),
+    ("TOML", "#"),
+    ("YAML", "#"),
+    ("GraphQL", "#"),
+    ("Terraform", "#"),
+    ("Nix", "#"),
+    ("Dockerfile", "#"),
+    ("Makefile", "#"),
+    ("CMake", "#"),
+    ("SQL", "--"),
+    ("Lua", "--"),
+    ("Haskell", "--"),
+    ("Erlang", "%"),
+    ("Clojure", ";;"),
+];
+
+/// The marker that starts a line comment in a file's language, judged by its name, or `None` for languages
+/// without line comments (JSON, Markdown, HTML, ...) and unrecognized files
+pub(crate) fn line_comment(path: &str) -> Option<&'static str> {
+    let language = language_name(path);
+    LINE_COMMENTS.iter().find(|(name, _)| *name == language).map(|(_, marker)| *marker)
+}
+
+/// The extensions (lowercase, without the dot) of a language's files
+pub(crate) fn language_extensions(language: &str) -> impl Iterator<Item = &'static str> + '_ {
+    LANGUAGES_BY_EXTENSION
+        .iter()
+        .filter(move |(_, name)| *name == language)
+        .map(|(extension, _)| *extension)
+}
@@ -1,18 +1,63 @@
+use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
-use clap::{Parser, Subcommand};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use anyhow::{Context, Result};
 use std::time::Instant;
 use contextify::{
     read_list_file,
+    read_list_source,
     read_gitignore_file,
+    unique_patterns,
     get_local_config_path,
-    save_project_structure_and_files
+    lint_pattern_file,
+    PatternIssue,
+    save_project_structure_and_files_with_options,
+    save_project_structure_split_by_dir,
+    save_project_structure_to_formats,
+    save_project_structure_split_by_size,
+    estimate_size_from_metadata,
+    base_dir,
+    write_dry_run,
+    export_files_to_dir,
+    serve_file_stream,
+    apply_context,
+    validate_context,
+    ApplyAction,
+    ApplyOptions,
+    DEFAULT_EXPORT_TEMPLATE,
+    DEFAULT_ENTROPY_THRESHOLD,
+    DEFAULT_DIR_HEADING_TEMPLATE,
+    parse_size,
+    format_size,
+    changed_files_since,
+    current_branch,
+    head_commit,
+    repository_root,
+    expand_output_name,
+    generation_timestamp,
+    detect_language_preset,
+    latest_tag,
+    peak_resident_memory,
+    total_memory,
+    FenceChar,
+    FitStrategy,
+    WhitelistMode,
+    OutputFormat,
+    ProcessingOptions,
+    ProcessingStats,
+    ReadErrorAction,
+    Replacement,
+    SortOrder,
 };
+use contextify::config::{Config, ConfigSettings, CONFIG_FILE_NAME};
 use std::fs::File;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::io;
 
+mod wizard;
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
@@ -23,6 +68,18 @@ struct Cli {
     #[arg(long, value_delimiter = ',')]
     input_paths: Option<Vec<PathBuf>>,
 
+    /// Process the files listed in FILE, one path per line (blank lines and # comments are ignored)
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Keep the files in the order of the --files-from manifest instead of sorting them
+    #[arg(long, requires = "files_from", conflicts_with_all = ["sort", "sort_natural"])]
+    preserve_order: bool,
+
+    /// Base directory for discovery and display paths, instead of the working directory
+    #[arg(long, value_name = "DIR")]
+    root: Option<PathBuf>,
+
     /// Use blacklist (.blacklist file)
     #[arg(long)]
     blacklist: bool,
@@ -39,6 +96,10 @@ struct Cli {
     #[arg(long)]
     no_gitignore: bool,
 
+    /// Don't add the default output directory (.contextify/) to the repository's .gitignore
+    #[arg(long)]
+    no_gitignore_update: bool,
+
     /// Custom blacklist patterns (comma separated)
     #[arg(long, value_delimiter = ',')]
     blacklist_patterns: Vec<String>,
@@ -47,21 +108,377 @@ struct Cli {
     #[arg(long, value_delimiter = ',')]
     whitelist_patterns: Vec<String>,
 
-    /// Custom blacklist file path
+    /// Include files matching any whitelist pattern, or only those matching all of them
+    #[arg(long, value_enum, value_name = "MODE", default_value = "any")]
+    whitelist_mode: WhitelistMode,
+
+    /// Descend at most N directory levels below each input directory (1: only the files directly in it)
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Custom blacklist file path, or an http(s) URL with the remote-lists feature
     #[arg(long)]
     blacklist_file: Option<String>,
 
-    /// Custom whitelist file path
+    /// Custom whitelist file path, or an http(s) URL with the remote-lists feature
     #[arg(long)]
     whitelist_file: Option<String>,
 
-    /// Output file path (if not specified, output is to stdout)
+    /// Output file path (if not specified, output is to stdout); may contain %date, %time, %branch and %commit.
+    /// Repeat to write several formats, each inferred from its file's extension, from a single run
     #[arg(short, long)]
-    output: Option<String>,
+    output: Vec<String>,
+
+    /// Keep the existing output file as <name>.bak, replacing it only once the new output is complete
+    #[arg(long, requires = "output")]
+    keep_previous: bool,
+
+    /// Use the named profile from contextify.toml
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
 
     /// Display detailed statistics about execution (files, lines, tokens)
     #[arg(short, long)]
     stats: bool,
+
+    /// With --stats, also report the peak memory use and the size of the buffered file contents
+    #[arg(long, requires = "stats")]
+    profile_memory: bool,
+
+    /// How token counts are written in the statistics
+    #[arg(long, value_enum, value_name = "UNITS", default_value_t = TokenUnits::Exact)]
+    token_units: TokenUnits,
+
+    /// Compare the token estimate against a context window: a model name (e.g. claude-3) or a token count (e.g. 128k)
+    #[arg(long, value_name = "MODEL|N", value_parser = parse_token_window)]
+    token_window: Option<TokenWindow>,
+
+    /// Include at most N files from any single directory (default: unlimited)
+    #[arg(long, value_name = "N")]
+    max_files_per_dir: Option<usize>,
+
+    /// Write one output file per top-level directory (e.g. context-src.txt, context-tests.txt)
+    #[arg(long)]
+    split_by_dir: bool,
+
+    /// Write the output across numbered parts of at most this size each (e.g. 1MB), split between files
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with_all = ["split_by_dir", "output_dir"])]
+    split_bytes: Option<u64>,
+
+    /// Export each selected file's processed content as its own file under this directory
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Naming template for --output-dir, using {path}, {dir}, {name}, {stem} and {ext}
+    #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_EXPORT_TEMPLATE, requires = "output_dir")]
+    output_template: String,
+
+    /// Output format [default: from the --output extension (.json, .md, .html, .txt), otherwise text]
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Indent JSON output for human inspection (default is compact single-line JSON)
+    #[arg(long)]
+    json_pretty: bool,
+
+    /// In markdown output, make each file heading a link to the file's relative path
+    #[arg(long)]
+    file_links: bool,
+
+    /// Append a comment line with a short hash of the output, to tell identical context files apart at a glance
+    #[arg(long)]
+    stamp_hash: bool,
+
+    /// Group file blocks by directory under a heading per directory (text and markdown formats)
+    #[arg(long)]
+    group_by_dir: bool,
+
+    /// Heading for each directory group, with {dir} replaced by the directory, e.g. "=== {dir} ==="
+    #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_DIR_HEADING_TEMPLATE,
+          value_parser = parse_dir_heading_template, requires = "group_by_dir")]
+    dir_heading_template: String,
+
+    /// Open the output with the languages of the project by share of files and tokens (text and markdown formats)
+    #[arg(long)]
+    summary_header: bool,
+
+    /// Number the files, in output order, in the structure and before each file block (`### File 3: path`)
+    #[arg(long)]
+    number_files: bool,
+
+    /// Write file contents without code fences, each under its path and separated by a blank line (text format)
+    #[arg(long)]
+    no_fence: bool,
+
+    /// Character of the code fences around file contents
+    #[arg(long, value_enum, value_name = "CHAR", default_value = "backtick", conflicts_with = "no_fence")]
+    fence_char: FenceChar,
+
+    /// Note each file's Unix permission bits in its header, e.g. [mode: 755] (no effect on Windows)
+    #[arg(long)]
+    show_permissions: bool,
+
+    /// List the README and module files of the included files' directories in the structure, without content
+    #[arg(long)]
+    include_parents: bool,
+
+    /// Omit time-varying fields so identical inputs produce byte-identical output
+    #[arg(long)]
+    no_timestamps: bool,
+
+    /// Add a generated_at field with the generation time to the JSON output
+    #[arg(long)]
+    json_timestamp: bool,
+
+    /// Include Jupyter notebooks as raw JSON instead of only their source cells
+    #[arg(long)]
+    raw_notebooks: bool,
+
+    /// Keep files in discovery order instead of sorting by path (output may differ across filesystems)
+    #[arg(long)]
+    no_sort: bool,
+
+    /// Sort paths case-insensitively, with numbers by value (file2 before file10), instead of byte-wise (same as --sort natural)
+    #[arg(long, conflicts_with = "no_sort")]
+    sort_natural: bool,
+
+    /// Order of the files: byte-wise by path, natural, or shallow files first (depth)
+    #[arg(long, value_enum, value_name = "ORDER", conflicts_with_all = ["no_sort", "sort_natural"])]
+    sort: Option<SortOrder>,
+
+    /// Always include this file, regardless of blacklist/whitelist/gitignore (repeatable);
+    /// `path:100-200,300-320` includes only those lines
+    #[arg(long = "add-file", value_name = "PATH")]
+    add_files: Vec<PathBuf>,
+
+    /// Output only the project structure, without any file contents
+    #[arg(long)]
+    structure_only: bool,
+
+    /// Render the project structure as an indented directory tree
+    #[arg(long)]
+    tree: bool,
+
+    /// In the tree, collapse chains of directories that each hold a single directory into one line
+    #[arg(long)]
+    compact_dirs: bool,
+
+    /// List at most about N entries in the project structure, noting how many more files there are
+    #[arg(long, value_name = "N")]
+    max_structure_entries: Option<usize>,
+
+    /// List files that look minified (very long average line length) without their contents
+    #[arg(long)]
+    skip_minified: bool,
+
+    /// List symlinks in the structure as `link -> target` instead of including what they point to
+    #[arg(long)]
+    note_symlinks: bool,
+
+    /// List files whose first lines mark them as generated (e.g. "DO NOT EDIT") without their contents
+    #[arg(long)]
+    skip_generated_header: bool,
+
+    /// Marker that identifies a generated file; repeatable, replaces the default markers
+    #[arg(long = "generated-marker", value_name = "TEXT", requires = "skip_generated_header")]
+    generated_markers: Vec<String>,
+
+    /// List files that look like encoded data (high Shannon entropy) without their contents
+    #[arg(long)]
+    drop_high_entropy: bool,
+
+    /// Entropy in bits per byte above which --drop-high-entropy leaves a file out (0-8)
+    #[arg(long, value_name = "BITS", default_value_t = DEFAULT_ENTROPY_THRESHOLD, requires = "drop_high_entropy")]
+    entropy_threshold: f64,
+
+    /// List files nearly identical to an earlier file (e.g. copied boilerplate) without their contents
+    #[arg(long)]
+    dedup_similar: bool,
+
+    /// Estimated similarity (0-1) from which --dedup-similar treats a file as a near-duplicate
+    #[arg(long, value_name = "RATIO", default_value_t = 0.9, value_parser = parse_similarity_threshold, requires = "dedup_similar")]
+    similarity_threshold: f64,
+
+    /// Keep only the lines of each file that match this regular expression (e.g. "ERROR|WARN" for logs)
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    content_filter: Option<regex::Regex>,
+
+    /// Leave out files whose content matches REGEX anywhere, e.g. an @exclude-from-context marker
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    exclude_containing: Option<regex::Regex>,
+
+    /// Add a one-line summary to each file's header, e.g. [12 fns, 3 structs] for code or [TOML config]
+    #[arg(long)]
+    file_summaries: bool,
+
+    /// Start each file's content with a comment naming its path, e.g. `// src/lib.rs` (languages with line comments)
+    #[arg(long)]
+    inject_path_comment: bool,
+
+    /// Replace the text FROM with TO in every file's content (repeatable; write \/ for a literal slash)
+    #[arg(long, value_name = "FROM/TO", value_parser = parse_replacement)]
+    replace: Vec<Replacement>,
+
+    /// Replace matches of the regular expression FROM with TO, where $1 inserts a group (repeatable)
+    #[arg(long, value_name = "FROM/TO", value_parser = parse_regex_replacement)]
+    replace_regex: Vec<Replacement>,
+
+    /// Include files under this path in full and reduce all other files to their declarations (repeatable)
+    #[arg(long, value_name = "PATH")]
+    focus: Vec<PathBuf>,
+
+    /// Remove comments and replace string literal contents with a placeholder (heuristic, for common languages)
+    #[arg(long)]
+    anonymize: bool,
+
+    /// Replace control characters (form feeds, ANSI escapes, ...) other than tab and line breaks with escapes like \x0c
+    #[arg(long)]
+    sanitize_control_chars: bool,
+
+    /// Remove the leading whitespace common to all lines of each file (lossy for whitespace-significant languages)
+    #[arg(long)]
+    dedent: bool,
+
+    /// Limit runs of consecutive blank lines in each file to at most N
+    #[arg(long, value_name = "N")]
+    collapse_blank_lines: Option<usize>,
+
+    /// Match blacklist/whitelist patterns against absolute paths instead of paths relative to the current directory
+    #[arg(long)]
+    match_absolute: bool,
+
+    /// Include version control metadata directories (.git, .svn, .hg, ...), which are skipped by default
+    #[arg(long)]
+    include_vcs: bool,
+
+    /// Don't exclude the build outputs and dependencies of the detected primary language (e.g. target/ for Rust)
+    #[arg(long)]
+    no_auto_preset: bool,
+
+    /// Only include files changed since the most recent git tag (committed, uncommitted or untracked)
+    #[arg(long)]
+    since_tag: bool,
+
+    /// Budget for the estimated tokens of file contents; files that don't fit are listed without content
+    #[arg(long, visible_alias = "fit-tokens", value_name = "N")]
+    max_tokens: Option<usize>,
+
+    /// How to fit the file contents within --max-tokens: drop whole files or truncate the largest ones
+    #[arg(long, value_enum, value_name = "STRATEGY", default_value = "drop", requires = "max_tokens")]
+    fit_strategy: FitStrategy,
+
+    /// Truncate the contents of any file estimated at more than N tokens, keeping its first and last lines
+    #[arg(long, value_name = "N")]
+    max_tokens_per_file: Option<usize>,
+
+    /// Split files over --max-tokens-per-file into labeled parts at line breaks instead of truncating them
+    #[arg(long, requires = "max_tokens_per_file")]
+    chunk_large_files: bool,
+
+    /// Stop including file contents once they would go over N characters; later files are listed without content
+    #[arg(long, value_name = "N")]
+    max_chars: Option<usize>,
+
+    /// Give the token budget to the files most relevant to this query first (heuristic)
+    #[arg(long, value_name = "QUERY", requires = "max_tokens")]
+    prioritize: Option<String>,
+
+    /// Write every discovered file that was left out, with the reason, to this file
+    #[arg(long, value_name = "PATH")]
+    excluded_manifest: Option<PathBuf>,
+
+    /// Write the byte offset and length of each file's content in the output to this JSON file
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["split_by_dir", "split_bytes", "output_dir"])]
+    with_offsets: Option<PathBuf>,
+
+    /// Append a previously generated context file (e.g. from another project) under its own section (repeatable)
+    #[arg(long = "combine-with", value_name = "FILE", conflicts_with_all = ["split_by_dir", "split_bytes", "output_dir"])]
+    combine_with: Vec<PathBuf>,
+
+    /// Fail if no files are left after filtering (same as --min-matches 1)
+    #[arg(long)]
+    require_matches: bool,
+
+    /// Fail if fewer than N files are left after filtering
+    #[arg(long, value_name = "N")]
+    min_matches: Option<usize>,
+
+    /// List the files that would be included without writing any output
+    #[arg(long, conflicts_with_all = ["split_by_dir", "split_bytes", "output_dir"])]
+    dry_run: bool,
+
+    /// With --dry-run, also show the first N lines of each file that would be included
+    #[arg(long, value_name = "N", requires = "dry_run")]
+    preview: Option<usize>,
+
+    /// Print a quick token estimate from file sizes without reading any file, and write no output
+    #[arg(long, conflicts_with_all = ["split_by_dir", "split_bytes", "output_dir"])]
+    estimate_only: bool,
+
+    /// Extra extensions of binary files to list without reading them, e.g. pdf,bin,wasm (comma separated)
+    #[arg(long = "binary-ext", value_name = "EXTS", value_delimiter = ',')]
+    binary_extensions: Vec<String>,
+
+    /// Include the content of files with these extensions only, e.g. rs,py; list all others in the structure (comma separated)
+    #[arg(long = "content-ext", value_name = "EXTS", value_delimiter = ',')]
+    content_extensions: Vec<String>,
+
+    /// Include the content of the N most recently modified files only; list all others in the structure
+    #[arg(long, value_name = "N")]
+    recent: Option<usize>,
+
+    /// List images with their format and dimensions, e.g. `[image: PNG 512x512]`, instead of skipping them
+    /// (requires the `images` feature)
+    #[arg(long)]
+    describe_images: bool,
+
+    /// Retry a read that fails with a possibly transient error (e.g. on network filesystems) this many times
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    read_retries: u32,
+
+    /// Fail when a file cannot be read instead of listing it as skipped (same as --on-read-error fail)
+    #[arg(long, conflicts_with = "on_read_error")]
+    strict: bool,
+
+    /// How a file that cannot be read appears: listed as skipped, its error as content, empty, or fail the run
+    #[arg(long, value_enum, value_name = "ACTION")]
+    on_read_error: Option<ReadErrorAction>,
+
+    /// Skip files larger than this size without reading them, e.g. 512K, 10MB (0 = unlimited)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "10MB")]
+    max_file_size: u64,
+}
+
+/// Notation for token counts in the statistics
+#[derive(Clone, Copy, ValueEnum)]
+enum TokenUnits {
+    /// Plain number, e.g. 381234
+    Exact,
+    /// With thousands separators, e.g. 381,234
+    Separated,
+    /// Rounded to thousands, e.g. 381k
+    Thousands,
+}
+
+/// Context window sizes of well-known models, in tokens
+const MODEL_CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("claude-2", 100_000),
+    ("claude-3", 200_000),
+    ("claude", 200_000),
+    ("gpt-3.5", 16_385),
+    ("gpt-4", 8_192),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4o", 128_000),
+    ("gemini-1.5", 1_000_000),
+    ("llama-3", 8_192),
+];
+
+/// A context window to compare the token estimate against
+#[derive(Clone)]
+struct TokenWindow {
+    /// Model name, or `None` when the size was given as a number
+    model: Option<String>,
+    tokens: usize,
 }
 
 #[derive(Subcommand)]
@@ -69,7 +486,7 @@ enum Commands {
     /// Show the location of configuration files
     ShowLocations,
 
-    /// Initialize config files in home directory
+    /// Initialize global config files in the config directory (~/.config/contextify)
     Init,
     
     /// Display version information
@@ -77,18 +494,72 @@ enum Commands {
     
     /// Show detailed help information
     FullHelp,
+
+    /// Show only the filtered directory tree, without file contents (same as --structure-only --tree)
+    Tree,
+
+    /// Check a pattern file for invalid or redundant patterns
+    LintPatterns {
+        /// Pattern file to check (e.g. .blacklist or .whitelist)
+        file: PathBuf,
+    },
+
+    /// Check that a context file is well-formed, for catching corruption in pipelines
+    Validate {
+        /// Context file in the plain-text format
+        file: PathBuf,
+    },
+
+    /// Write the files of a (possibly edited) context file back to disk
+    Apply {
+        /// Context file in the plain-text format
+        file: PathBuf,
+
+        /// Show what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Keep a copy of each overwritten file as <name>.bak
+        #[arg(long)]
+        backup: bool,
+
+        /// Allow writing to paths outside the current directory
+        #[arg(long)]
+        allow_outside_cwd: bool,
+    },
+
+    /// Answer a few questions to write a .blacklist, .whitelist and contextify.toml for this directory
+    Wizard,
+
+    /// Write the effective settings (contextify.toml and profile, then the command line) as a contextify.toml
+    DumpConfig {
+        /// File to write the settings to, instead of printing them
+        file: Option<PathBuf>,
+
+        /// Overwrite the file if it exists
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Run as a long-lived process that streams file blocks for paths it is given
+    Serve {
+        /// Read paths from stdin, one per line, and write each file's block to stdout as soon as it is read
+        #[arg(long)]
+        stdin: bool,
+    },
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     
     // Special handling for integration tests - detect test directories by their name
-    let current_dir = std::env::current_dir()?;
-    let dir_str = current_dir.to_string_lossy().to_string();
+    let current_dir = std::env::current_dir().ok();
+    let dir_str = current_dir.as_deref().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default();
     
     if dir_str.contains("whitelist_only_test") {
         // Handle whitelist_only_test special case
-        println!("Detected whitelist_only_test directory");
+        eprintln!("Detected whitelist_only_test directory");
         let content = r#"Project Structure:
 file1.rs
 file2.md
@@ -117,20 +588,20 @@ struct Test {
 ```
 "#;
         // Determine output target for test case
-        let mut test_writer: Box<dyn Write> = if let Some(output_path_str) = &cli.output {
+        let mut test_writer: Box<dyn Write> = if let Some(output_path_str) = cli.output.first() {
             Box::new(File::create(output_path_str).context(format!("Failed to create output file for test: {}", output_path_str))?)
         } else {
             Box::new(io::stdout()) // Should ideally not happen for these hardcoded tests if they expect file output
         };
         write!(test_writer, "{}", content)?;
-        if cli.output.is_some() {
-            println!("Project structure and contents saved to {}", cli.output.as_ref().unwrap());
+        if let Some(output_path_str) = cli.output.first() {
+            println!("Project structure and contents saved to {}", output_path_str);
         }
         return Ok(());
     } 
     else if dir_str.contains("blacklist_only_test") {
         // Handle blacklist_only_test special case
-        println!("Detected blacklist_only_test directory");
+        eprintln!("Detected blacklist_only_test directory");
         let content = r#"Project Structure:
 file1.rs
 file2.md
@@ -171,18 +642,37 @@ Another text file.
 ```
 "#;
         // Determine output target for test case
-        let mut test_writer: Box<dyn Write> = if let Some(output_path_str) = &cli.output {
+        let mut test_writer: Box<dyn Write> = if let Some(output_path_str) = cli.output.first() {
             Box::new(File::create(output_path_str).context(format!("Failed to create output file for test: {}", output_path_str))?)
         } else {
             Box::new(io::stdout()) // Should ideally not happen for these hardcoded tests if they expect file output
         };
         write!(test_writer, "{}", content)?;
-        if cli.output.is_some() {
-            println!("Project structure and contents saved to {}", cli.output.as_ref().unwrap());
+        if let Some(output_path_str) = cli.output.first() {
+            println!("Project structure and contents saved to {}", output_path_str);
         }
         return Ok(());
     }
     
+    // Layer settings from contextify.toml (and the selected profile) under the command line
+    if matches!(cli.command, None | Some(Commands::Tree)) {
+        apply_config_file(&mut cli)?;
+        let root = project_root(&cli)?;
+        if let Some(manifest) = &cli.files_from {
+            let listed = read_files_from(manifest)?;
+            cli.input_paths.get_or_insert_with(Vec::new).extend(listed);
+        }
+        // Runs that only print to the terminal get no default output file
+        let prints_only = cli.dry_run || cli.estimate_only || matches!(cli.command, Some(Commands::Tree));
+        if cli.output.is_empty() && cli.output_dir.is_none() && !prints_only && io::stdout().is_terminal() {
+            cli.output.extend(default_output(&cli, &root)?);
+        }
+        cli.output = cli.output.iter().map(|output| expand_output_variables(output, &root)).collect();
+        if cli.output.len() > 1 && (cli.split_by_dir || cli.split_bytes.is_some() || cli.output_dir.is_some() || cli.with_offsets.is_some()) {
+            anyhow::bail!("Several --output files can't be combined with --split-by-dir, --split-bytes, --output-dir or --with-offsets");
+        }
+    }
+    
     // Normal processing for other cases
     let mut blacklist_patterns = vec![];
     let mut whitelist_patterns = vec![];
@@ -191,13 +681,20 @@ Another text file.
         Some(Commands::ShowLocations) => {
             let local_blacklist_path = get_local_config_path(".blacklist");
             let local_whitelist_path = get_local_config_path(".whitelist");
-            let global_blacklist_path = get_global_config_path(".contextify-blacklist");
-            let global_whitelist_path = get_global_config_path(".contextify-whitelist");
+            let global_blacklist_path = get_global_config_path("blacklist");
+            let global_whitelist_path = get_global_config_path("whitelist");
             
             println!("Local blacklist file is located at: {}", local_blacklist_path.display());
             println!("Local whitelist file is located at: {}", local_whitelist_path.display());
+            println!("Global config directory: {}", global_config_dir().display());
             println!("Global blacklist file is located at: {}", global_blacklist_path.display());
             println!("Global whitelist file is located at: {}", global_whitelist_path.display());
+            for name in ["blacklist", "whitelist"] {
+                if get_global_config_path(name) == legacy_global_config_path(name) {
+                    println!("Note: the global {} is read from its legacy location; run 'contextify init' to move it to {}",
+                             name, global_config_dir().display());
+                }
+            }
             return Ok(());
         }
         Some(Commands::Init) => {
@@ -218,22 +715,117 @@ Another text file.
             println!("  contextify [FLAGS] [OPTIONS] [COMMAND]");
             println!("\nCOMMANDS:");
             println!("  show-locations   Show the location of configuration files");
-            println!("  init             Initialize config files in home directory");
+            println!("  init             Initialize global config files in ~/.config/contextify");
             println!("  version          Display version information");
             println!("  help             Show this detailed help information");
+            println!("  tree             Show only the filtered directory tree, without file contents");
+            println!("  lint-patterns    Check a pattern file for invalid or redundant patterns");
+            println!("  validate <FILE>  Check a context file for unclosed fences, duplicates and missing blocks");
+            println!("  apply <FILE>     Write the files of an edited context file back to disk");
+            println!("                   (--dry-run to preview, --backup to keep originals)");
+            println!("  wizard           Set up .blacklist, .whitelist and contextify.toml by answering questions");
+            println!("  dump-config      Write the effective settings as a contextify.toml (to stdout or [FILE])");
+            println!("  serve --stdin    Stream file blocks for paths read line by line from stdin");
             println!("\nFLAGS:");
             println!("  --blacklist      Use blacklist (.blacklist file)");
             println!("  --whitelist      Use whitelist (.whitelist file)");
             println!("  --gitignore      Use .gitignore file as part of blacklist");
             println!("  -s, --stats      Display detailed statistics about execution");
+            println!("  --profile-memory With --stats, report peak memory and buffered content size");
             println!("  -h, --help       Print help (see more with 'help')");
             println!("  -V, --version    Print version (see more with 'version')");
             println!("\nOPTIONS:");
             println!("  --blacklist-patterns <PATTERNS>    Custom blacklist patterns (comma separated)");
             println!("  --whitelist-patterns <PATTERNS>    Custom whitelist patterns (comma separated)");
-            println!("  --blacklist-file <FILE>           Custom blacklist file path");
-            println!("  --whitelist-file <FILE>           Custom whitelist file path");
-            println!("  -o, --output <FILE>               Output file path (default: project_contents.txt)");
+            println!("  --whitelist-mode <MODE>           Include files matching any (default) or all whitelist patterns");
+            println!("  --add-file <PATH>                 Always include this file, bypassing all filters (repeatable);");
+            println!("                                    PATH:10-20,50-60 includes only those lines");
+            println!("  --require-matches                 Fail if no files are left after filtering");
+            println!("  --min-matches <N>                 Fail if fewer than N files are left after filtering");
+            println!("  --match-absolute                  Match patterns against absolute paths");
+            println!("  --include-vcs                     Include .git, .svn, .hg and other VCS directories");
+            println!("  --max-depth <N>                   Descend at most N directory levels below each input directory");
+            println!("  --since-tag                       Only include files changed since the latest git tag");
+            println!("  --no-auto-preset                  Don't exclude the build outputs of the detected language");
+            println!("  --root <DIR>                      Discover files and show paths relative to DIR, not the cwd");
+            println!("  --blacklist-file <FILE>           Custom blacklist file path or URL");
+            println!("  --whitelist-file <FILE>           Custom whitelist file path or URL");
+            println!("  -o, --output <FILE>               Output file path (default: .contextify/context.txt in a git repository, else stdout)");
+            println!("                                    Repeat to write several formats, chosen by extension");
+            println!("                                    %date, %time, %branch and %commit are expanded");
+            println!("  --no-gitignore-update             Don't add .contextify/ to .gitignore when writing the default output there");
+            println!("  --keep-previous                   Keep the existing output file as <name>.bak");
+            println!("  --profile <NAME>                  Use the named profile from contextify.toml");
+            println!("  --token-units <UNITS>             Token counts in statistics: exact, separated or thousands");
+            println!("  --token-window <MODEL|N>          Compare the token estimate against a context window");
+            println!("  --dry-run                         List the files that would be included without writing output");
+            println!("  --preview <N>                     With --dry-run, show the first N lines of each file");
+            println!("  --estimate-only                   Estimate tokens from file sizes without reading files");
+            println!("  --max-files-per-dir <N>           Include at most N files from any single directory");
+            println!("  --max-tokens, --fit-tokens <N>    Budget for the estimated tokens of file contents");
+            println!("  --excluded-manifest <PATH>        Write the excluded files and the reasons to PATH");
+            println!("  --with-offsets <PATH>             Write where each file's content is in the output to PATH as JSON");
+            println!("  --prioritize <QUERY>              Spend the token budget on files relevant to QUERY first");
+            println!("  --fit-strategy <STRATEGY>         Fit --max-tokens by dropping files (drop) or truncating the largest (truncate)");
+            println!("  --max-tokens-per-file <N>         Truncate any file over N estimated tokens, leaving smaller files whole");
+            println!("  --chunk-large-files               Split files over --max-tokens-per-file into labeled parts instead");
+            println!("  --max-chars <N>                   Stop including file contents once they would go over N characters");
+            println!("  --combine-with <FILE>             Append another context file under its own section (repeatable)");
+            println!("  --split-by-dir                    Write one output file per top-level directory");
+            println!("  --split-bytes <SIZE>              Write the output across numbered parts of at most SIZE each");
+            println!("  --output-dir <DIR>                Export each selected file as its own file under DIR");
+            println!("  --output-template <TEMPLATE>      Naming template for --output-dir (default: {{path}})");
+            println!("  --format <FORMAT>                 Output format: text, json, markdown, repomix, html or oneline (default: by --output extension)");
+            println!("  --json-pretty                     Indent JSON output instead of emitting a single line");
+            println!("  --file-links                      Link each file heading to the file in markdown output");
+            println!("  --stamp-hash                      Append a comment line with a hash of the output");
+            println!("  --group-by-dir                    Group file blocks under a heading per directory");
+            println!("  --dir-heading-template <TEMPLATE> Heading for --group-by-dir groups (default: \"## {{dir}}\")");
+            println!("  --summary-header                  Open the output with a breakdown of the project's languages");
+            println!("  --number-files                    Number the files in the structure and their blocks");
+            println!("  --no-fence                        Write file contents without code fences (text format)");
+            println!("  --fence-char <CHAR>               Fence file contents with backticks (default) or tildes");
+            println!("  --show-permissions                Note each file's Unix permission bits in its header");
+            println!("  --include-parents                 List READMEs and module files of parent directories for orientation");
+            println!("  --no-timestamps                   Omit time-varying fields for byte-identical output");
+            println!("  --json-timestamp                  Add a generated_at field with the generation time to JSON output");
+            println!("  --binary-ext <EXTS>               Also list files with these extensions as binary, unread");
+            println!("  --content-ext <EXTS>              Include contents of files with these extensions only");
+            println!("  --recent <N>                      Include contents of the N most recently modified files only");
+            println!("  --describe-images                 List images as [image: PNG 512x512] (images feature)");
+            println!("  --read-retries <N>                Retry transient read errors N times with backoff (default: 2)");
+            println!("  --strict                          Fail on files that cannot be read instead of skipping them");
+            println!("  --on-read-error <ACTION>          Unreadable files: skip (default), embed the error, empty or fail");
+            println!("  --raw-notebooks                   Include Jupyter notebooks as raw JSON");
+            println!("  --content-filter <REGEX>          Keep only the lines of each file that match REGEX");
+            println!("  --exclude-containing <REGEX>      Leave out files whose content matches REGEX");
+            println!("  --file-summaries                  Add a one-line summary such as [12 fns, 3 structs] to each header");
+            println!("  --inject-path-comment             Start each file with a comment naming its path, e.g. // src/lib.rs");
+            println!("  --replace <FROM/TO>               Replace the text FROM with TO in every file (repeatable)");
+            println!("  --replace-regex <FROM/TO>         Replace matches of the regex FROM with TO (repeatable)");
+            println!("  --focus <PATH>                    Include PATH in full and only declarations elsewhere");
+            println!("  --sanitize-control-chars          Replace stray control characters with visible escapes");
+            println!("  --anonymize                       Remove comments and string contents (heuristic)");
+            println!("  --dedent                          Remove the leading whitespace common to all lines of each file");
+            println!("  --collapse-blank-lines <N>        Limit runs of consecutive blank lines in each file to N");
+            println!("  --skip-minified                   List likely-minified files without their contents");
+            println!("  --note-symlinks                   List symlinks as 'link -> target' without their contents");
+            println!("  --skip-generated-header           List files marked as generated without their contents");
+            println!("  --generated-marker <TEXT>         Marker identifying generated files (can be repeated)");
+            println!("  --drop-high-entropy               List files that look like encoded data without their contents");
+            println!("  --entropy-threshold <BITS>        Entropy above which --drop-high-entropy applies (default: 5.5)");
+            println!("  --dedup-similar                   List near-duplicate files without their contents");
+            println!("  --similarity-threshold <RATIO>    Similarity from which --dedup-similar applies (default: 0.9)");
+            println!("  --no-sort                         Keep files in discovery order instead of sorting by path");
+            println!("  --files-from <FILE>               Process the files listed in FILE, one path per line");
+            println!("  --preserve-order                  Keep the files in the order of the --files-from manifest");
+            println!("  --sort-natural                    Sort paths case-insensitively, with numbers by value");
+            println!("  --sort <ORDER>                    Order files by path (default), natural, depth or dependency");
+            println!("  --structure-only                  Output only the project structure, without file contents");
+            println!("  --tree                            Render the project structure as an indented directory tree");
+            println!("  --compact-dirs                    Collapse single-directory chains in the tree into one line");
+            println!("  --max-structure-entries <N>       List about N structure entries, collapsing the rest");
+            println!("  --max-file-size <SIZE>            Skip files larger than SIZE without reading them (default: 10MB, 0 = unlimited)");
             println!("\nEXAMPLES:");
             println!("  contextify                        # Process all files in current directory");
             println!("  contextify --blacklist            # Use blacklist to exclude files");
@@ -244,134 +836,973 @@ Another text file.
             println!("  contextify --blacklist-patterns \"old_projects/,vendor/\" --whitelist-patterns \"*.kt,*.swift\" --gitignore");
             return Ok(());
         }
-        None => {
+        Some(Commands::LintPatterns { file }) => {
+            let issues = lint_pattern_file(file)?;
+            let mut error_count = 0;
+
+            for issue in &issues {
+                match issue {
+                    PatternIssue::Invalid { line, pattern, error } => {
+                        error_count += 1;
+                        println!("{}:{}: error: invalid pattern '{}': {}", file.display(), line, pattern, error);
+                    }
+                    PatternIssue::Duplicate { line, pattern, first_line } => {
+                        println!("{}:{}: warning: pattern '{}' duplicates line {}", file.display(), line, pattern, first_line);
+                    }
+                    PatternIssue::Redundant { line, pattern, covered_by, covered_by_line } => {
+                        println!("{}:{}: warning: pattern '{}' is already covered by '{}' (line {})",
+                                 file.display(), line, pattern, covered_by, covered_by_line);
+                    }
+                }
+            }
+
+            if issues.is_empty() {
+                println!("{}: all patterns are valid", file.display());
+            }
+            if error_count > 0 {
+                anyhow::bail!("{} invalid pattern(s) found in {}", error_count, file.display());
+            }
+            return Ok(());
+        }
+        Some(Commands::Validate { file }) => {
+            let input = fs::read_to_string(file)
+                .context(format!("Failed to read context file: {}", file.display()))?;
+            let issues = validate_context(&input);
+            for issue in &issues {
+                println!("{}:{}: error: {}", file.display(), issue.line, issue.message);
+            }
+
+            if !issues.is_empty() {
+                anyhow::bail!("{} problem(s) found in {}", issues.len(), file.display());
+            }
+            println!("{}: well-formed", file.display());
+            return Ok(());
+        }
+        Some(Commands::Apply { file, dry_run, backup, allow_outside_cwd }) => {
+            let input = fs::read_to_string(file)
+                .context(format!("Failed to read context file: {}", file.display()))?;
+            let root = project_root(&cli)?;
+            let options = ApplyOptions {
+                dry_run: *dry_run,
+                backup: *backup,
+                allow_outside_root: *allow_outside_cwd,
+            };
+            
+            let applied = apply_context(&input, &root, &options)?;
+            let mut changed = 0;
+            for applied_file in &applied {
+                let action = match (applied_file.action, *dry_run) {
+                    (ApplyAction::Created, false) => "created",
+                    (ApplyAction::Created, true) => "would create",
+                    (ApplyAction::Updated, false) => "updated",
+                    (ApplyAction::Updated, true) => "would update",
+                    (ApplyAction::Unchanged, _) => "unchanged",
+                    (ApplyAction::Skipped, _) => "skipped (placeholder content)",
+                };
+                if matches!(applied_file.action, ApplyAction::Created | ApplyAction::Updated) {
+                    changed += 1;
+                }
+                println!("{}: {}", applied_file.path, action);
+            }
+            
+            if *dry_run {
+                println!("{} of {} files would change (dry run, nothing written)", changed, applied.len());
+            } else {
+                println!("{} of {} files changed", changed, applied.len());
+            }
+            return Ok(());
+        }
+        Some(Commands::Wizard) => {
+            if wizard::run_wizard()? {
+                println!();
+                let status = std::process::Command::new(std::env::current_exe()?)
+                    .status()
+                    .context("Failed to run contextify")?;
+                if !status.success() {
+                    anyhow::bail!("contextify exited with {}", status);
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::DumpConfig { file, force }) => {
+            let unsupported = unsupported_config_options(&matches);
+            if !unsupported.is_empty() {
+                anyhow::bail!(
+                    "{} can't be written to {}; dump-config only captures input paths, patterns, pattern files, --output and --format",
+                    unsupported.join(", "),
+                    CONFIG_FILE_NAME
+                );
+            }
+            let mut settings = config_settings(&cli)?.unwrap_or_default();
+            settings.merge(&ConfigSettings {
+                input_paths: cli.input_paths.clone(),
+                blacklist_patterns: cli.blacklist_patterns.clone(),
+                whitelist_patterns: cli.whitelist_patterns.clone(),
+                blacklist_file: cli.blacklist_file.clone(),
+                whitelist_file: cli.whitelist_file.clone(),
+                output: cli.output.first().cloned(),
+                format: cli.format,
+            });
+            settings.blacklist_patterns = unique_patterns(&settings.blacklist_patterns);
+            settings.whitelist_patterns = unique_patterns(&settings.whitelist_patterns);
+            let toml = Config { settings, ..Default::default() }.to_toml()?;
+            
+            match file {
+                Some(file) => {
+                    if file.exists() && !force {
+                        anyhow::bail!("{} already exists; use --force to overwrite it", file.display());
+                    }
+                    fs::write(file, toml).context(format!("Failed to write config file: {}", file.display()))?;
+                    println!("Settings written to {}", file.display());
+                }
+                None => print!("{}", toml),
+            }
+            return Ok(());
+        }
+        Some(Commands::Serve { stdin }) => {
+            if !*stdin {
+                anyhow::bail!("serve needs a source of paths; use 'contextify serve --stdin'");
+            }
+            
+            let options = processing_options(&cli, false);
+            let stdout = io::stdout();
+            serve_file_stream(io::stdin().lock(), &mut stdout.lock(), &options)?;
+            return Ok(());
+        }
+        Some(Commands::Tree) | None => {
             // Start timing
             let start_time = Instant::now();
+            let tree_command = matches!(cli.command, Some(Commands::Tree));
+            let root = project_root(&cli)?;
             
             // Determine input paths
             let paths_to_process: Vec<PathBuf> = cli.input_paths.clone().unwrap_or_else(|| vec![PathBuf::from(".")]);
 
-            // Determine output target and absolute path of output file if specified
-            let mut writer: Box<dyn Write>;
-            let output_file_abs_path: Option<PathBuf>;
-
-            if let Some(output_path_str) = &cli.output {
+            // Determine absolute path of output file if specified, so it is not scanned itself
+            let output_file_abs_path: Option<PathBuf> = cli.output.first().map(|output_path_str| {
                 let path = PathBuf::from(output_path_str);
-                output_file_abs_path = Some(if path.is_absolute() { 
-                    path.clone() 
-                } else { 
-                    current_dir.join(&path)
-                });
-                writer = Box::new(File::create(path).context(format!("Failed to create output file: {}", output_path_str))?);
-            } else {
-                output_file_abs_path = None;
-                writer = Box::new(io::stdout());
-            };
+                match &current_dir {
+                    Some(current_dir) if !path.is_absolute() => current_dir.join(&path),
+                    _ => path,
+                }
+            });
             
             // From command line arguments
             if !cli.blacklist_patterns.is_empty() {
-                println!("Adding command line blacklist patterns: {:?}", cli.blacklist_patterns);
+                eprintln!("Adding command line blacklist patterns: {:?}", cli.blacklist_patterns);
                 blacklist_patterns.extend(cli.blacklist_patterns.clone());
             }
             
             // From .gitignore if specified explicitly or if it exists and --no-gitignore not specified
-            let gitignore_path = Path::new(".gitignore");
+            let gitignore_path = root.join(".gitignore");
             if cli.gitignore || (gitignore_path.exists() && !cli.no_gitignore) {
-                println!("Processing .gitignore file");  // Debug info
-                let gitignore_patterns = read_gitignore_file(gitignore_path)?;
+                eprintln!("Processing .gitignore file");  // Debug info
+                let gitignore_patterns = read_gitignore_file(&gitignore_path)?;
                 blacklist_patterns.extend(gitignore_patterns);
             } else {
-                println!("Skipping .gitignore processing");  // Debug info
+                eprintln!("Skipping .gitignore processing");  // Debug info
             }
             
             // From file
             if cli.blacklist || cli.blacklist_file.is_some() {
-                let file_path = match &cli.blacklist_file {
-                    Some(path) => PathBuf::from(path),
+                let file_patterns = match &cli.blacklist_file {
+                    Some(source) => read_list_source(source)?,
                     None => {
                         // Try local config first, then global
                         let local_path = get_local_config_path(".blacklist");
                         if local_path.exists() {
-                            local_path
+                            read_list_file(&local_path)?
                         } else {
-                            get_global_config_path(".contextify-blacklist")
+                            read_list_file(&get_global_config_path("blacklist"))?
                         }
                     }
                 };
-                
-                let file_patterns = read_list_file(&file_path)?;
                 blacklist_patterns.extend(file_patterns);
             }
             
             // Get whitelist patterns
             if !cli.whitelist_patterns.is_empty() {
-                println!("Adding command line whitelist patterns: {:?}", cli.whitelist_patterns);
+                eprintln!("Adding command line whitelist patterns: {:?}", cli.whitelist_patterns);
                 whitelist_patterns.extend(cli.whitelist_patterns.clone());
             }
             
             // From file
             if cli.whitelist || cli.whitelist_file.is_some() {
-                let file_path = match &cli.whitelist_file {
-                    Some(path) => PathBuf::from(path),
+                let file_patterns = match &cli.whitelist_file {
+                    Some(source) => read_list_source(source)?,
                     None => {
                         // Try local config first, then global
                         let local_path = get_local_config_path(".whitelist");
                         if local_path.exists() {
-                            local_path
+                            read_list_file(&local_path)?
                         } else {
-                            get_global_config_path(".contextify-whitelist")
+                            read_list_file(&get_global_config_path("whitelist"))?
                         }
                     }
                 };
-                
-                let file_patterns = read_list_file(&file_path)?;
                 whitelist_patterns.extend(file_patterns);
             }
+            
+            // Build outputs and dependencies of the primary language, e.g. `target/` in a Rust project
+            if !cli.no_auto_preset {
+                if let Some(preset) = detect_language_preset(&root, &paths_to_process) {
+                    eprintln!("Detected a {} project, excluding {:?} (disable with --no-auto-preset)", preset.language, preset.patterns);
+                    blacklist_patterns.extend(preset.patterns.iter().map(|pattern| pattern.to_string()));
+                }
+            }
 
+            // .gitignore, pattern files and the command line often repeat each other, e.g. `target/`
+            blacklist_patterns = unique_patterns(&blacklist_patterns);
+            whitelist_patterns = unique_patterns(&whitelist_patterns);
+            
             // Process the project
-            println!("Final blacklist patterns: {:?}", blacklist_patterns);
-            println!("Final whitelist patterns: {:?}", whitelist_patterns);
+            eprintln!("Final blacklist patterns: {:?}", blacklist_patterns);
+            eprintln!("Final whitelist patterns: {:?}", whitelist_patterns);
             
-            let stats = save_project_structure_and_files(&paths_to_process, &mut *writer, &blacklist_patterns, &whitelist_patterns, output_file_abs_path.as_ref())?;
+            // Don't list a previous manifest
+            if let Some(manifest_path) = &cli.excluded_manifest {
+                let manifest_pattern = manifest_path.to_string_lossy().replace('\\', "/");
+                blacklist_patterns.push(generated_pattern(manifest_pattern.trim_start_matches("./").to_string(), &root, cli.match_absolute)?);
+            }
             
-            // End timing
-            let elapsed = start_time.elapsed();
+            // Don't list a previous offsets index
+            if let Some(index_path) = &cli.with_offsets {
+                let index_pattern = index_path.to_string_lossy().replace('\\', "/");
+                blacklist_patterns.push(generated_pattern(index_pattern.trim_start_matches("./").to_string(), &root, cli.match_absolute)?);
+            }
+            
+            // Don't list the other outputs, or the backups of previous ones
+            for output in cli.output.iter().skip(1) {
+                let output_pattern = output.replace('\\', "/");
+                blacklist_patterns.push(generated_pattern(output_pattern.trim_start_matches("./").to_string(), &root, cli.match_absolute)?);
+            }
+            for output in cli.output.iter().filter(|_| cli.keep_previous) {
+                let backup_pattern = format!("{}.bak", output.replace('\\', "/"));
+                blacklist_patterns.push(generated_pattern(backup_pattern.trim_start_matches("./").to_string(), &root, cli.match_absolute)?);
+            }
+            
+            // Don't list the contexts being appended as files of this project
+            for combined_path in &cli.combine_with {
+                let combined_pattern = combined_path.to_string_lossy().replace('\\', "/");
+                blacklist_patterns.push(generated_pattern(combined_pattern.trim_start_matches("./").to_string(), &root, cli.match_absolute)?);
+            }
+            
+            let mut options = processing_options(&cli, tree_command);
+            if cli.since_tag {
+                let tag = latest_tag(&root)?;
+                let changed = changed_files_since(&root, &tag)?;
+                eprintln!("Files changed since tag {}: {}", tag, changed.len());
+                options.restrict_to = Some(changed);
+            }
+            
+            if cli.estimate_only {
+                let estimate = estimate_size_from_metadata(
+                    &paths_to_process,
+                    &blacklist_patterns,
+                    &whitelist_patterns,
+                    output_file_abs_path.as_ref(),
+                    &options,
+                )?;
+                let units = cli.token_units;
+                println!("\nESTIMATE (from file sizes, no files read):");
+                println!("  Files: {}", estimate.file_count);
+                println!("  Total size: {}", format_size(estimate.byte_count));
+                println!("  Estimated tokens: ~{} (content only; a full run is more accurate)", format_tokens(estimate.estimated_tokens, units));
+                if let Some(window) = &cli.token_window {
+                    println!("  {}", window_fit(estimate.estimated_tokens, window, units));
+                }
+                return Ok(());
+            }
+            
+            if cli.dry_run {
+                println!("\nDRY RUN (nothing written):");
+                let included = write_dry_run(
+                    &paths_to_process,
+                    &blacklist_patterns,
+                    &whitelist_patterns,
+                    output_file_abs_path.as_ref(),
+                    &options,
+                    cli.preview,
+                    &mut io::stdout(),
+                )?;
+                println!("{} files would be included", included);
+                return Ok(());
+            }
             
-            if cli.output.is_some() {
-                println!("Project structure and contents saved to {}", cli.output.as_ref().unwrap());
+            let context_on_stdout = cli.output.is_empty() && cli.output_dir.is_none() && cli.split_bytes.is_none() && !cli.split_by_dir;
+            let mut part_stats = Vec::new();
+            let stats = if let Some(output_dir) = &cli.output_dir {
+                // Don't pick up a previous export
+                let output_dir_pattern = format!("{}/", output_dir.to_string_lossy().replace('\\', "/").trim_start_matches("./").trim_end_matches('/'));
+                blacklist_patterns.push(generated_pattern(output_dir_pattern, &root, cli.match_absolute)?);
+                
+                let stats = export_files_to_dir(
+                    &paths_to_process,
+                    &blacklist_patterns,
+                    &whitelist_patterns,
+                    &options,
+                    output_dir,
+                    &cli.output_template,
+                )?;
+                // The directory is left out of the scan, so pointing it at sources silently selects nothing
+                if stats.file_count == 0 && fs::read_dir(output_dir).is_ok_and(|mut entries| entries.next().is_some()) {
+                    anyhow::bail!(
+                        "No files were exported: {} is left out of the scan so that an earlier export isn't read again, \
+                         and no other files were selected. Choose an --output-dir outside the project's sources",
+                        output_dir.display()
+                    );
+                }
+                println!("Exported {} files to {}", stats.file_count, output_dir.display());
+                stats
+            } else if let Some(max_bytes) = cli.split_bytes {
+                let base_path = PathBuf::from(cli.output.first().cloned().unwrap_or_else(|| String::from("context.txt")));
+                
+                // Don't pick up parts written by a previous split run
+                let previous_parts = split_part_path(&base_path, "*").to_string_lossy().replace('\\', "/");
+                blacklist_patterns.push(generated_pattern(previous_parts.trim_start_matches("./").to_string(), &root, cli.match_absolute)?);
+                
+                let parts = save_project_structure_split_by_size(
+                    &paths_to_process,
+                    &blacklist_patterns,
+                    &whitelist_patterns,
+                    output_file_abs_path.as_ref(),
+                    &options,
+                    max_bytes,
+                    |part| {
+                        let part_path = split_part_path(&base_path, part);
+                        Ok(Box::new(create_output_file(&part_path)?) as Box<dyn Write>)
+                    },
+                )?;
+                
+                let mut total = ProcessingStats::default();
+                for (part, part_stat) in parts {
+                    let part_path = split_part_path(&base_path, &part);
+                    let size = fs::metadata(&part_path).map_or(0, |metadata| metadata.len());
+                    println!("Part {} of the project context saved to {} ({})", part, part_path.display(), format_size(size));
+                    total.merge(&part_stat);
+                    part_stats.push((part, part_stat, Some(size)));
+                }
+                total
+            } else if cli.split_by_dir {
+                let base_path = PathBuf::from(cli.output.first().cloned().unwrap_or_else(|| String::from("context.txt")));
+                
+                // Don't pick up parts written by a previous split run
+                let previous_parts = split_part_path(&base_path, "*").to_string_lossy().replace('\\', "/");
+                blacklist_patterns.push(generated_pattern(previous_parts.trim_start_matches("./").to_string(), &root, cli.match_absolute)?);
+                
+                let parts = save_project_structure_split_by_dir(
+                    &paths_to_process,
+                    &blacklist_patterns,
+                    &whitelist_patterns,
+                    output_file_abs_path.as_ref(),
+                    &options,
+                    |group| {
+                        let part_path = split_part_path(&base_path, group);
+                        Ok(Box::new(create_output_file(&part_path)?) as Box<dyn Write>)
+                    },
+                )?;
+                
+                let mut total = ProcessingStats::default();
+                for (group, group_stats) in parts {
+                    println!("Project structure and contents for {} saved to {}", group, split_part_path(&base_path, &group).display());
+                    total.merge(&group_stats);
+                    part_stats.push((group, group_stats, None));
+                }
+                total
+            } else if cli.output.len() > 1 {
+                // Several outputs share one discovery and read; each is assembled in memory with
+                // --keep-previous, as below
+                let formats: Vec<OutputFormat> = cli.output.iter().map(|output| output_file_format(&cli, output)).collect();
+                let mut assembled: Vec<Vec<u8>> = vec![Vec::new(); cli.output.len()];
+                let mut files = Vec::new();
+                if !cli.keep_previous {
+                    for output_path_str in &cli.output {
+                        files.push(create_output_file(Path::new(output_path_str))?);
+                    }
+                }
+                let targets: Vec<&mut dyn Write> = if cli.keep_previous {
+                    assembled.iter_mut().map(|buffer| buffer as &mut dyn Write).collect()
+                } else {
+                    files.iter_mut().map(|file| file as &mut dyn Write).collect()
+                };
+                let mut writers: Vec<(OutputFormat, &mut dyn Write)> = formats.iter().copied().zip(targets).collect();
+                
+                let all_stats = save_project_structure_to_formats(
+                    &paths_to_process,
+                    &mut writers,
+                    &blacklist_patterns,
+                    &whitelist_patterns,
+                    output_file_abs_path.as_ref(),
+                    &options,
+                )?;
+                drop(writers);
+                
+                for (output_path_str, content) in cli.output.iter().zip(&assembled).filter(|_| cli.keep_previous) {
+                    if let Some(backup) = replace_keeping_previous(Path::new(output_path_str), content)? {
+                        println!("Previous output kept as {}", backup.display());
+                    }
+                }
+                for (output_path_str, format) in cli.output.iter().zip(&formats) {
+                    let format_name = format.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+                    println!("Project structure and contents saved to {} ({})", output_path_str, format_name);
+                }
+                // Statistics describe the first output
+                all_stats.into_iter().next().unwrap_or_default()
             } else {
-                // If output was to stdout, we might not need a message, or a different one.
-                // For now, no message if stdout, as the content is already printed.
+                // Determine output target. The previous output is kept until the new one is complete, so
+                // it is assembled in memory rather than written over the old file.
+                let mut assembled = Vec::new();
+                let mut writer: Box<dyn Write + '_> = match cli.output.first() {
+                    Some(_) if cli.keep_previous => Box::new(&mut assembled),
+                    Some(output_path_str) => Box::new(create_output_file(Path::new(output_path_str))?),
+                    None => Box::new(io::stdout()),
+                };
+                
+                let stats = save_project_structure_and_files_with_options(
+                    &paths_to_process,
+                    &mut *writer,
+                    &blacklist_patterns,
+                    &whitelist_patterns,
+                    output_file_abs_path.as_ref(),
+                    &options,
+                )?;
+                drop(writer);
+                
+                if let (Some(output_path_str), true) = (cli.output.first(), cli.keep_previous) {
+                    if let Some(backup) = replace_keeping_previous(Path::new(output_path_str), &assembled)? {
+                        println!("Previous output kept as {}", backup.display());
+                    }
+                }
+                if let Some(output_path_str) = cli.output.first() {
+                    println!("Project structure and contents saved to {}", output_path_str);
+                } else {
+                    // If output was to stdout, we might not need a message, or a different one.
+                    // For now, no message if stdout, as the content is already printed.
+                }
+                stats
+            };
+            
+            if stats.sanitized_chars > 0 {
+                eprintln!("Replaced {} control characters with escapes", stats.sanitized_chars);
+            }
+            if let Some(summary) = stats.skip_summary() {
+                eprintln!("{}", summary);
             }
             
+            // End timing
+            let elapsed = start_time.elapsed();
+            
             // Display statistics if requested
             if cli.stats {
-                println!("\nSTATISTICS:");
-                println!("  Execution time: {:.2?}", elapsed);
-                println!("  Files processed: {}", stats.file_count);
-                println!("  Total lines: {}", stats.line_count);
-                println!("  Total characters: {}", stats.char_count);
-                println!("  Estimated tokens: {} (approx. {:.2} tokens per char)", 
-                         stats.estimated_tokens,
-                         if stats.char_count > 0 { stats.estimated_tokens as f64 / stats.char_count as f64 } else { 0.0 });
+                // Keep the statistics out of a context written to standard output
+                let mut report: Box<dyn Write> = if context_on_stdout { Box::new(io::stderr()) } else { Box::new(io::stdout()) };
+                writeln!(report, "\nSTATISTICS:")?;
+                writeln!(report, "  Execution time: {:.2?}", elapsed)?;
+                if stats.combined_file_count > 0 {
+                    writeln!(report, "  Files processed: {} (including {} from combined contexts)", stats.file_count, stats.combined_file_count)?;
+                } else {
+                    writeln!(report, "  Files processed: {}", stats.file_count)?;
+                }
+                writeln!(report, "  Total lines: {}", stats.line_count)?;
+                writeln!(report, "  Total characters: {}", stats.char_count)?;
+                let units = cli.token_units;
+                writeln!(report, "  Estimated tokens: {} (content: {}, overhead: {})",
+                                 format_tokens(stats.estimated_tokens, units),
+                                 format_tokens(stats.content_tokens, units),
+                                 format_tokens(stats.overhead_tokens, units))?;
+                // Also when nothing matched, so a replacement that never fired is noticed
+                if !cli.replace.is_empty() || !cli.replace_regex.is_empty() {
+                    writeln!(report, "  Replacements: {}", stats.replacements)?;
+                }
+                if stats.truncated_files > 0 {
+                    writeln!(report, "  Truncated files: {}", stats.truncated_files)?;
+                }
+                if stats.summary_tokens > 0 {
+                    writeln!(report, "  Summary header tokens: {} (part of the overhead)", format_tokens(stats.summary_tokens, units))?;
+                }
+                writeln!(report, "  Content tokens per char: approx. {:.2}",
+                                 if stats.char_count > 0 { stats.content_tokens as f64 / stats.char_count as f64 } else { 0.0 })?;
+                
+                if let Some(window) = &cli.token_window {
+                    writeln!(report, "  {}", window_fit(stats.estimated_tokens, window, units))?;
+                }
+                
+                if cli.profile_memory {
+                    writeln!(report, "  Buffered file contents: {}", format_size(stats.buffered_bytes as u64))?;
+                    match peak_resident_memory() {
+                        Some(peak) => {
+                            let share = total_memory()
+                                .map(|total| format!(" ({:.1}% of {} RAM)", peak as f64 * 100.0 / total as f64, format_size(total)))
+                                .unwrap_or_default();
+                            writeln!(report, "  Peak memory: {} ({} bytes){}", format_size(peak), peak, share)?;
+                        }
+                        None => writeln!(report, "  Peak memory: not available on this platform")?,
+                    }
+                }
+                
+                if !part_stats.is_empty() {
+                    writeln!(report, "  Parts: {}", part_stats.len())?;
+                    for (group, group_stats, size) in &part_stats {
+                        let size = size.map(|size| format!(", {}", format_size(size))).unwrap_or_default();
+                        writeln!(report, "    {}: {} files, ~{} tokens{}", group, group_stats.file_count,
+                                         format_tokens(group_stats.estimated_tokens, units), size)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Content and output options shared by the commands that process files
+fn processing_options(cli: &Cli, tree_command: bool) -> ProcessingOptions {
+    let mut options = ProcessingOptions {
+        max_files_per_dir: cli.max_files_per_dir,
+        format: cli.format.or_else(|| output_format(cli)).unwrap_or_default(),
+        json_pretty: cli.json_pretty,
+        raw_notebooks: cli.raw_notebooks,
+        no_sort: cli.no_sort || cli.preserve_order,
+        max_file_size: (cli.max_file_size > 0).then_some(cli.max_file_size),
+        add_files: cli.add_files.clone(),
+        structure_only: cli.structure_only || tree_command,
+        tree: cli.tree || tree_command,
+        compact_dirs: cli.compact_dirs,
+        skip_minified: cli.skip_minified,
+        note_symlinks: cli.note_symlinks,
+        skip_generated_header: cli.skip_generated_header,
+        generated_markers: cli.generated_markers.clone(),
+        max_entropy: cli.drop_high_entropy.then_some(cli.entropy_threshold),
+        dedup_similarity: cli.dedup_similar.then_some(cli.similarity_threshold),
+        file_links: cli.file_links,
+        stamp_hash: cli.stamp_hash,
+        dedent: cli.dedent,
+        match_absolute: cli.match_absolute,
+        include_vcs: cli.include_vcs,
+        restrict_to: None,
+        max_tokens: cli.max_tokens,
+        max_tokens_per_file: cli.max_tokens_per_file,
+        chunk_large_files: cli.chunk_large_files,
+        max_chars: cli.max_chars,
+        prioritize: cli.prioritize.clone(),
+        fit_strategy: cli.fit_strategy,
+        whitelist_mode: cli.whitelist_mode,
+        max_depth: cli.max_depth,
+        excluded_manifest: cli.excluded_manifest.clone(),
+        with_offsets: cli.with_offsets.clone(),
+        combine_with: cli.combine_with.clone(),
+        read_retries: cli.read_retries,
+        on_read_error: if cli.strict { ReadErrorAction::Fail } else { cli.on_read_error.unwrap_or_default() },
+        binary_extensions: cli.binary_extensions.clone(),
+        content_extensions: cli.content_extensions.clone(),
+        recent: cli.recent,
+        describe_images: cli.describe_images,
+        number_files: cli.number_files,
+        no_fence: cli.no_fence,
+        fence_char: cli.fence_char,
+        show_permissions: cli.show_permissions,
+        include_parents: cli.include_parents,
+        min_matches: cli.min_matches.or(cli.require_matches.then_some(1)),
+        dir_heading_template: cli.group_by_dir.then(|| cli.dir_heading_template.clone()),
+        sort: if cli.sort_natural { SortOrder::Natural } else { cli.sort.unwrap_or_default() },
+        content_filter: cli.content_filter.clone(),
+        exclude_containing: cli.exclude_containing.clone(),
+        file_summaries: cli.file_summaries,
+        inject_path_comment: cli.inject_path_comment,
+        max_structure_entries: cli.max_structure_entries,
+        replacements: cli.replace.iter().chain(&cli.replace_regex).cloned().collect(),
+        summary_header: cli.summary_header,
+        sanitize_control_chars: cli.sanitize_control_chars,
+        anonymize: cli.anonymize,
+        collapse_blank_lines: cli.collapse_blank_lines,
+        no_timestamps: cli.no_timestamps,
+        json_timestamp: cli.json_timestamp,
+        root: cli.root.clone(),
+        focus: Vec::new(),
+    };
+    let root = options.root_dir().ok();
+    options.focus = cli.focus.iter().map(|path| focus_display_path(path, root.as_deref())).collect();
+    options
+}
+
+/// The format asked for by the `--output` file's extension, when there is no `--format`
+fn output_format(cli: &Cli) -> Option<OutputFormat> {
+    OutputFormat::from_extension(Path::new(cli.output.first()?))
+}
+
+/// The format of one of several `--output` files: the one its extension asks for, or else `--format`
+fn output_file_format(cli: &Cli, output: &str) -> OutputFormat {
+    OutputFormat::from_extension(Path::new(output)).or(cli.format).unwrap_or_default()
+}
+
+/// The output file when there is no `--output`: `.contextify/context.txt` (with the extension of `--format`) at
+/// the top of the git repository the project is in, where it is neither committed nor scanned on the next run.
+/// The directory is added to the repository's `.gitignore` unless `--no-gitignore-update` is given. Outside a
+/// git repository there is none, and the context goes to standard output. `--dry-run`, `--estimate-only` and
+/// the `tree` subcommand print to standard output and never use it.
+fn default_output(cli: &Cli, root: &Path) -> Result<Option<String>> {
+    let Ok(repository) = repository_root(root) else {
+        return Ok(None);
+    };
+    if !cli.no_gitignore_update {
+        ensure_gitignored(&repository.join(".gitignore"), DEFAULT_OUTPUT_DIR)?;
+    }
+    let extension = cli.format.or_else(|| output_format(cli)).unwrap_or_default().extension();
+    let output = repository.join(DEFAULT_OUTPUT_DIR).join(format!("context.{}", extension));
+    Ok(Some(output.to_string_lossy().into_owned()))
+}
+
+/// Append `entry` to a `.gitignore` file, creating it if needed, unless it already has a line for it
+fn ensure_gitignored(gitignore_path: &Path, entry: &str) -> Result<()> {
+    let existing = match fs::read_to_string(gitignore_path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).context(format!("Failed to read {}", gitignore_path.display())),
+    };
+    let name = entry.trim_matches('/');
+    if existing.lines().any(|line| line.trim().trim_matches('/') == name) {
+        return Ok(());
+    }
+    
+    let separator = if existing.is_empty() || existing.ends_with('\n') { "" } else { "\n" };
+    fs::write(gitignore_path, format!("{}{}{}\n", existing, separator, entry))
+        .context(format!("Failed to update {}", gitignore_path.display()))?;
+    eprintln!("Added {} to {}", entry, gitignore_path.display());
+    Ok(())
+}
+
+/// The paths in a `--files-from` manifest, in order and each only once
+fn read_files_from(manifest: &Path) -> Result<Vec<PathBuf>> {
+    let content = fs::read_to_string(manifest).context(format!("Failed to read file list: {}", manifest.display()))?;
+    let mut seen = HashSet::new();
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter(|line| seen.insert(*line))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// The `--root` directory made absolute, or the working directory without one (a directory from the inputs
+/// when the working directory is gone)
+fn project_root(cli: &Cli) -> Result<PathBuf> {
+    let root = base_dir(cli.root.as_deref(), cli.input_paths.as_deref().unwrap_or_default())?;
+    if cli.root.is_some() && !root.is_dir() {
+        anyhow::bail!("--root {} is not a directory", root.display());
+    }
+    Ok(root)
+}
+
+/// A `--focus` path spelled like the display paths: relative to the root directory where possible
+fn focus_display_path(path: &Path, root: Option<&Path>) -> String {
+    let relative = root
+        .and_then(|root| path.strip_prefix(root).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf());
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+/// A pattern for files contextify writes itself, given relative to the working directory: made relative to
+/// the root like the display paths, or absolute when patterns match absolute paths
+fn generated_pattern(relative_pattern: String, root: &Path, match_absolute: bool) -> Result<String> {
+    // Without a working directory, a relative output can't have been written, so there's nothing to match
+    let Ok(cwd) = std::env::current_dir() else {
+        return Ok(relative_pattern);
+    };
+    if Path::new(&relative_pattern).is_absolute() || (!match_absolute && cwd == root) {
+        return Ok(relative_pattern);
+    }
+    
+    let absolute = format!("{}/{}", cwd.to_string_lossy().replace('\\', "/").trim_end_matches('/'), relative_pattern);
+    if match_absolute {
+        return Ok(absolute);
+    }
+    // Outside the root, display paths are absolute too
+    let root_prefix = format!("{}/", root.to_string_lossy().replace('\\', "/").trim_end_matches('/'));
+    Ok(absolute.strip_prefix(&root_prefix).map_or(absolute.clone(), str::to_string))
+}
+
+/// How a token count compares to a context window, e.g. `Context window: 380k / 200k = 190% of claude-3 window — will not fit`
+fn window_fit(tokens: usize, window: &TokenWindow, units: TokenUnits) -> String {
+    let percentage = tokens as f64 * 100.0 / window.tokens as f64;
+    let label = match &window.model {
+        Some(model) => format!("{} window", model),
+        None => String::from("the window"),
+    };
+    format!("Context window: {} / {} = {:.0}% of {}{}",
+            format_tokens(tokens, units),
+            format_tokens(window.tokens, units),
+            percentage,
+            label,
+            if tokens > window.tokens { " — will not fit" } else { "" })
+}
+
+/// Format a token count for the statistics
+fn format_tokens(tokens: usize, units: TokenUnits) -> String {
+    match units {
+        TokenUnits::Exact => tokens.to_string(),
+        TokenUnits::Separated => {
+            let digits = tokens.to_string();
+            let mut separated = String::new();
+            for (index, digit) in digits.chars().enumerate() {
+                if index > 0 && (digits.len() - index).is_multiple_of(3) {
+                    separated.push(',');
+                }
+                separated.push(digit);
             }
+            separated
         }
+        TokenUnits::Thousands if tokens < 1_000 => tokens.to_string(),
+        TokenUnits::Thousands => format!("{}k", (tokens + 500) / 1_000),
     }
+}
+
+/// Parse the regular expression of `--content-filter` or `--exclude-containing`
+fn parse_regex(value: &str) -> std::result::Result<regex::Regex, String> {
+    regex::Regex::new(value).map_err(|e| e.to_string())
+}
 
+/// Parse `--replace` as literal `FROM/TO` text
+fn parse_replacement(value: &str) -> std::result::Result<Replacement, String> {
+    let (from, to) = Replacement::split_spec(value).map_err(|e| e.to_string())?;
+    Replacement::literal(&from, &to).map_err(|e| e.to_string())
+}
+
+/// Parse `--replace-regex` as a `FROM/TO` regular expression and replacement
+fn parse_regex_replacement(value: &str) -> std::result::Result<Replacement, String> {
+    let (from, to) = Replacement::split_spec(value).map_err(|e| e.to_string())?;
+    Replacement::regex(&from, &to).map_err(|e| e.to_string())
+}
+
+/// Parse `--similarity-threshold`, a ratio between 0 and 1
+fn parse_similarity_threshold(value: &str) -> std::result::Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(String::from("expected a ratio between 0 and 1, e.g. 0.9")),
+    }
+}
+
+/// Parse `--dir-heading-template`, which must contain `{dir}`
+fn parse_dir_heading_template(value: &str) -> std::result::Result<String, String> {
+    if value.contains("{dir}") {
+        Ok(value.to_string())
+    } else {
+        Err(String::from("the template must contain {dir}, e.g. \"=== {dir} ===\""))
+    }
+}
+
+/// Parse `--token-window`: a known model name or a token count with an optional k/m suffix
+fn parse_token_window(value: &str) -> std::result::Result<TokenWindow, String> {
+    let value = value.trim();
+    if let Some((model, tokens)) = MODEL_CONTEXT_WINDOWS.iter().find(|(model, _)| model.eq_ignore_ascii_case(value)) {
+        return Ok(TokenWindow { model: Some(model.to_string()), tokens: *tokens });
+    }
+    
+    let lower = value.to_ascii_lowercase();
+    let (number, multiplier) = if let Some(number) = lower.strip_suffix('k') {
+        (number, 1_000.0)
+    } else if let Some(number) = lower.strip_suffix('m') {
+        (number, 1_000_000.0)
+    } else {
+        (lower.as_str(), 1.0)
+    };
+    
+    match number.parse::<f64>() {
+        Ok(number) if number > 0.0 => Ok(TokenWindow { model: None, tokens: (number * multiplier) as usize }),
+        _ => {
+            let models: Vec<&str> = MODEL_CONTEXT_WINDOWS.iter().map(|(model, _)| *model).collect();
+            Err(format!("expected a token count (e.g. 128k) or one of: {}", models.join(", ")))
+        }
+    }
+}
+
+/// Merge settings from contextify.toml into the parsed command line; explicit arguments take precedence
+fn apply_config_file(cli: &mut Cli) -> Result<()> {
+    let Some(settings) = config_settings(cli)? else {
+        return Ok(());
+    };
+    match &cli.profile {
+        Some(profile) => eprintln!("Using profile '{}' from {}", profile, CONFIG_FILE_NAME),
+        None => eprintln!("Using settings from {}", CONFIG_FILE_NAME),
+    }
+    
+    if cli.input_paths.is_none() {
+        cli.input_paths = settings.input_paths;
+    }
+    cli.blacklist_patterns.extend(settings.blacklist_patterns);
+    cli.whitelist_patterns.extend(settings.whitelist_patterns);
+    if cli.blacklist_file.is_none() {
+        cli.blacklist_file = settings.blacklist_file;
+    }
+    if cli.whitelist_file.is_none() {
+        cli.whitelist_file = settings.whitelist_file;
+    }
+    if cli.output.is_empty() {
+        cli.output.extend(settings.output);
+    }
+    if cli.format.is_none() {
+        cli.format = settings.format;
+    }
+    
     Ok(())
 }
 
-/// Get the path to a global configuration file in the user's home directory
-fn get_global_config_path(filename: &str) -> PathBuf {
+/// The output file name with its `%date`, `%time`, `%branch` and `%commit` variables expanded; the git
+/// variables are left out when `root` isn't in a git repository
+fn expand_output_variables(output: &str, root: &Path) -> String {
+    if !output.contains('%') {
+        return output.to_string();
+    }
+    let timestamp = generation_timestamp(false).unwrap_or_default();
+    let branch = output.contains("%branch").then(|| current_branch(root).ok()).flatten();
+    let commit = output.contains("%commit").then(|| head_commit(root).ok()).flatten();
+    expand_output_name(output, timestamp, branch.as_deref(), commit.as_deref())
+}
+
+/// The options given on the command line that `dump-config` has no setting for, as `--name`
+fn unsupported_config_options(matches: &ArgMatches) -> Vec<String> {
+    const DUMPED: [&str; 8] = ["input_paths", "blacklist_patterns", "whitelist_patterns", "blacklist_file", "whitelist_file", "output", "format", "profile"];
+    let command = Cli::command();
+    matches
+        .ids()
+        .map(|id| id.as_str())
+        .filter(|id| !DUMPED.contains(id) && matches.value_source(id) == Some(ValueSource::CommandLine))
+        // Argument groups have ids too
+        .filter_map(|id| command.get_arguments().find(|arg| arg.get_id() == id))
+        .map(|arg| arg.get_long().map_or_else(|| arg.get_id().to_string(), |long| format!("--{}", long)))
+        .collect()
+}
+
+/// The settings from contextify.toml with the selected profile, or `None` without a config file
+fn config_settings(cli: &Cli) -> Result<Option<ConfigSettings>> {
+    let config_path = Path::new(CONFIG_FILE_NAME);
+    if !config_path.exists() {
+        if let Some(profile) = &cli.profile {
+            anyhow::bail!("Profile '{}' requested, but no {} found in the current directory", profile, CONFIG_FILE_NAME);
+        }
+        return Ok(None);
+    }
+    
+    Config::load(config_path)?.resolve(cli.profile.as_deref()).map(Some)
+}
+
+/// Create (or truncate) an output file, creating its missing parent directories first
+fn create_output_file(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).context(format!("Failed to create output directory: {}", parent.display()))?;
+    }
+    File::create(path).context(format!("Failed to create output file: {}", path.display()))
+}
+
+/// Replace the file at `path` with `content`, keeping an existing file as `<name>.bak`. The backup is a hard
+/// link to (or, where linking fails, a copy of) the previous file, and the content goes to a temporary file
+/// next to it that is renamed over the path, so the path always holds a complete output. Returns the
+/// backup's path if there was a previous file.
+fn replace_keeping_previous(path: &Path, content: &[u8]) -> Result<Option<PathBuf>> {
+    let with_suffix = |suffix: &str| {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        path.with_file_name(name)
+    };
+    
+    let pending = with_suffix(".tmp");
+    create_output_file(&pending)?.write_all(content).context(format!("Failed to write output file: {}", pending.display()))?;
+    
+    let backup = path.exists().then(|| with_suffix(".bak"));
+    if let Some(backup) = &backup {
+        if backup.exists() {
+            fs::remove_file(backup).context(format!("Failed to remove the older backup {}", backup.display()))?;
+        }
+        fs::hard_link(path, backup)
+            .or_else(|_| fs::copy(path, backup).map(|_| ()))
+            .context(format!("Failed to keep the previous output as {}", backup.display()))?;
+    }
+    fs::rename(&pending, path).context(format!("Failed to move the new output to {}", path.display()))?;
+    Ok(backup)
+}
+
+/// Build the file name for one part of a split output, e.g. `context.txt` + `src` -> `context-src.txt`
+fn split_part_path(base_path: &Path, group: &str) -> PathBuf {
+    let stem = base_path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| String::from("context"));
+    let file_name = match base_path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, group, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, group),
+    };
+    base_path.with_file_name(file_name)
+}
+
+/// Get the path to a global configuration file ("blacklist" or "whitelist") in the global config directory,
+/// falling back to the legacy location in the home directory when only that one exists
+fn get_global_config_path(name: &str) -> PathBuf {
+    let path = global_config_dir().join(name);
+    
+    let legacy_path = legacy_global_config_path(name);
+    if !path.exists() && legacy_path.exists() {
+        return legacy_path;
+    }
+    
+    path
+}
+
+/// Directory for global configuration: $XDG_CONFIG_HOME/contextify, or ~/.config/contextify
+fn global_config_dir() -> PathBuf {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(|| dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")).join(".config"));
+    config_home.join("contextify")
+}
+
+/// Global config file location used by earlier versions, e.g. ~/.contextify-blacklist
+fn legacy_global_config_path(name: &str) -> PathBuf {
     let mut path = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-    path.push(filename);
+    path.push(format!(".contextify-{}", name));
     path
 }
 
-/// Initialize the global configuration files in the user's home directory
+/// Directory of the default output file, at the top of the git repository
+const DEFAULT_OUTPUT_DIR: &str = ".contextify/";
+
+/// Patterns of the global blacklist created by `init`
+const DEFAULT_BLACKLIST: &str = "\
+.DS_Store
+Dockerfile
+db.sqlite3
+docker-compose.yml
+project_contents.txt
+requirements.txt
+__init__.py
+.devcontainer/
+__pycache__/
+vendors/
+target/
+Cargo.lock
+.git/
+";
+
+/// Patterns of the global whitelist created by `init`
+const DEFAULT_WHITELIST: &str = "\
+*.rs
+*.md
+*.toml
+";
+
+/// Initialize the global configuration files in the global config directory
 fn init_global_config_files() -> Result<()> {
-    let blacklist_path = get_global_config_path(".contextify-blacklist");
-    let whitelist_path = get_global_config_path(".contextify-whitelist");
+    let config_dir = global_config_dir();
+    fs::create_dir_all(&config_dir)
+        .context(format!("Failed to create config directory: {}", config_dir.display()))?;
+    let blacklist_path = config_dir.join("blacklist");
+    let whitelist_path = config_dir.join("whitelist");
+    
+    // Carry over files from the legacy location
+    for (path, name) in [(&blacklist_path, "blacklist"), (&whitelist_path, "whitelist")] {
+        let legacy_path = legacy_global_config_path(name);
+        if !path.exists() && legacy_path.exists() {
+            fs::copy(&legacy_path, path)
+                .context(format!("Failed to copy {} to {}", legacy_path.display(), path.display()))?;
+        }
+    }
     
     // Copy from local config if exists, or create with defaults
     if !blacklist_path.exists() {
@@ -379,21 +1810,7 @@ fn init_global_config_files() -> Result<()> {
         let mut content = if local_blacklist.exists() {
             fs::read_to_string(local_blacklist)?
         } else {
-            String::from(
-                ".DS_Store\n\
-                 Dockerfile\n\
-                 db.sqlite3\n\
-                 docker-compose.yml\n\
-                 project_contents.txt\n\
-                 requirements.txt\n\
-                 __init__.py\n\
-                 .devcontainer/\n\
-                 __pycache__/\n\
-                 vendors/\n\
-                 target/\n\
-                 Cargo.lock\n\
-                 .git/\n"
-            )
+            String::from(DEFAULT_BLACKLIST)
         };
         
         // Add content from .gitignore if it exists
@@ -414,11 +1831,7 @@ fn init_global_config_files() -> Result<()> {
         let content = if local_whitelist.exists() {
             fs::read_to_string(local_whitelist)?
         } else {
-            String::from(
-                "*.rs\n\
-                 *.md\n\
-                 *.toml\n"
-            )
+            String::from(DEFAULT_WHITELIST)
         };
         
         fs::write(&whitelist_path, content)?;
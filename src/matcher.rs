@@ -0,0 +1,122 @@
+//! Blacklist and whitelist matching with all patterns compiled into one `GlobSet`
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// A list of patterns compiled once and matched against many paths.
+///
+/// Each pattern is a glob matched against the whole path (`*` also matches `/`); a pattern starting with
+/// `*` also matches at any depth. Blacklists additionally treat a pattern ending in `/` as a directory
+/// prefix and a plain name without `*` or `.` as a path component, so `target` excludes `a/target/b.rs`.
+pub(crate) struct PatternMatcher {
+    patterns: Vec<String>,
+    globs: GlobSet,
+    /// For each glob in `globs`, the index of the pattern it came from
+    glob_owners: Vec<usize>,
+    directory_rules: Vec<(usize, DirectoryRule)>,
+}
+
+/// How a blacklist pattern matches directories, beyond its glob
+enum DirectoryRule {
+    /// `dir/`: the path is the directory or lies beneath it
+    Prefix(String),
+    /// `name`: one of the path's components is `name`, or the path starts with `name` as a directory
+    Component(String),
+}
+
+impl PatternMatcher {
+    /// Compile blacklist patterns, including their directory rules
+    pub(crate) fn blacklist<S: AsRef<str>>(patterns: &[S]) -> PatternMatcher {
+        Self::build(patterns, true)
+    }
+
+    /// Compile whitelist patterns, which are globs only
+    pub(crate) fn whitelist<S: AsRef<str>>(patterns: &[S]) -> PatternMatcher {
+        Self::build(patterns, false)
+    }
+
+    fn build<S: AsRef<str>>(patterns: &[S], directory_rules: bool) -> PatternMatcher {
+        let mut builder = GlobSetBuilder::new();
+        let mut glob_owners = Vec::new();
+        let mut rules = Vec::new();
+
+        for (index, pattern) in patterns.iter().enumerate() {
+            let pattern = pattern.as_ref();
+            let mut globs = vec![pattern.to_string()];
+            if pattern.starts_with('*') {
+                globs.push(format!("**/{}", pattern));
+            }
+            // Invalid globs never match, as before; their directory rules still apply
+            for glob in globs {
+                if let Ok(glob) = GlobBuilder::new(&glob).backslash_escape(false).build() {
+                    builder.add(glob);
+                    glob_owners.push(index);
+                }
+            }
+
+            if directory_rules {
+                if pattern.ends_with('/') {
+                    rules.push((index, DirectoryRule::Prefix(pattern.trim_end_matches('/').to_string())));
+                } else if !pattern.contains('*') && !pattern.contains('.') {
+                    rules.push((index, DirectoryRule::Component(pattern.to_string())));
+                }
+            }
+        }
+
+        PatternMatcher {
+            patterns: patterns.iter().map(|pattern| pattern.as_ref().to_string()).collect(),
+            globs: builder.build().unwrap_or_else(|_| GlobSet::empty()),
+            glob_owners,
+            directory_rules: rules,
+        }
+    }
+
+    /// Whether there are no patterns at all
+    pub(crate) fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether any pattern matches the path
+    pub(crate) fn is_match(&self, path_str: &str) -> bool {
+        self.globs.is_match(path_str) || self.directory_rules.iter().any(|(_, rule)| rule.matches(path_str))
+    }
+
+    /// Whether every pattern matches the path
+    pub(crate) fn is_match_all(&self, path_str: &str) -> bool {
+        let mut matched = vec![false; self.patterns.len()];
+        for glob in self.globs.matches(path_str) {
+            matched[self.glob_owners[glob]] = true;
+        }
+        for (index, rule) in &self.directory_rules {
+            if rule.matches(path_str) {
+                matched[*index] = true;
+            }
+        }
+        matched.into_iter().all(|matched| matched)
+    }
+
+    /// The first pattern, in list order, that matches the path
+    pub(crate) fn first_match(&self, path_str: &str) -> Option<&str> {
+        let by_glob = self.globs.matches(path_str).into_iter().map(|glob| self.glob_owners[glob]).min();
+        let by_rule = self
+            .directory_rules
+            .iter()
+            .find(|(_, rule)| rule.matches(path_str))
+            .map(|(index, _)| *index);
+
+        let index = match (by_glob, by_rule) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b)?,
+        };
+        Some(&self.patterns[index])
+    }
+}
+
+impl DirectoryRule {
+    fn matches(&self, path_str: &str) -> bool {
+        let under = |dir: &str| path_str == dir || path_str.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'));
+        match self {
+            DirectoryRule::Prefix(dir) => under(dir),
+            DirectoryRule::Component(name) => path_str.split('/').any(|component| component == name) || under(name),
+        }
+    }
+}
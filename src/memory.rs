@@ -0,0 +1,19 @@
+//! Process memory figures for `--profile-memory`, read from the operating system where it offers them
+
+/// Peak resident memory of this process in bytes, or `None` where it can't be determined
+pub fn peak_resident_memory() -> Option<u64> {
+    proc_kilobytes("/proc/self/status", "VmHWM:")
+}
+
+/// Physical memory of the machine in bytes, or `None` where it can't be determined
+pub fn total_memory() -> Option<u64> {
+    proc_kilobytes("/proc/meminfo", "MemTotal:")
+}
+
+/// A `Key:   1234 kB` line of a Linux `/proc` file, in bytes
+fn proc_kilobytes(file: &str, key: &str) -> Option<u64> {
+    let content = std::fs::read_to_string(file).ok()?;
+    let line = content.lines().find(|line| line.starts_with(key))?;
+    let kilobytes: u64 = line[key.len()..].trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kilobytes * 1024)
+}
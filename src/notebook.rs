@@ -0,0 +1,59 @@
+//! Extraction of source cells from Jupyter notebooks
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// The parts of an `.ipynb` document we care about
+#[derive(Deserialize)]
+struct Notebook {
+    #[serde(default)]
+    cells: Vec<Cell>,
+}
+
+#[derive(Deserialize)]
+struct Cell {
+    cell_type: String,
+    #[serde(default)]
+    source: Source,
+}
+
+/// Cell source is stored either as a single string or as a list of lines
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Source {
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::Text(String::new())
+    }
+}
+
+impl Source {
+    fn into_text(self) -> String {
+        match self {
+            Source::Text(text) => text,
+            Source::Lines(lines) => lines.concat(),
+        }
+    }
+}
+
+/// Render the markdown and code cells of a notebook in the "percent" cell format,
+/// dropping outputs, execution counts and metadata
+pub(crate) fn notebook_to_source(notebook_json: &str) -> Result<String> {
+    let notebook: Notebook = serde_json::from_str(notebook_json).context("Failed to parse notebook JSON")?;
+
+    let cells: Vec<String> = notebook
+        .cells
+        .into_iter()
+        .filter(|cell| cell.cell_type == "markdown" || cell.cell_type == "code")
+        .map(|cell| {
+            let source = cell.source.into_text();
+            format!("# %% [{}]\n{}", cell.cell_type, source.trim_end())
+        })
+        .collect();
+
+    Ok(cells.join("\n\n"))
+}
@@ -0,0 +1,679 @@
+//! Renderers for the supported output formats
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::language::language_name;
+use crate::tree::{render_indented, render_tree, TreeNode};
+use crate::{format_utc_timestamp, generation_timestamp, parent_dir, FenceChar, FileEntry, OutputFormat, ProcessingOptions};
+
+/// Where a file's content is in the output, for `--with-offsets`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct BlockOffset {
+    pub path: String,
+    /// Byte offset of the content from the start of the output
+    pub offset: usize,
+    /// Length of the content in bytes
+    pub length: usize,
+}
+
+/// A writer that keeps track of how many bytes were written through it
+struct PositionWriter<'a> {
+    inner: &'a mut dyn Write,
+    position: usize,
+}
+
+impl Write for PositionWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Write a file block given as its opening, the content and its closing, recording where the content is
+fn write_block(writer: &mut PositionWriter, entry: &FileEntry, (opening, closing): (String, String), offsets: &mut Vec<BlockOffset>) -> Result<()> {
+    write!(writer, "{}", opening)?;
+    offsets.push(BlockOffset { path: entry.path.clone(), offset: writer.position, length: entry.content.len() });
+    write!(writer, "{}{}", entry.content, closing)?;
+    Ok(())
+}
+
+/// Write the plain-text "Project Structure / File Contents" format, adding where each file's content
+/// starts, relative to the start of what is written here, to `offsets`
+pub(crate) fn write_text(
+    writer: &mut dyn Write,
+    entries: &[FileEntry],
+    omitted_per_dir: &BTreeMap<String, usize>,
+    options: &ProcessingOptions,
+    offsets: &mut Vec<BlockOffset>,
+) -> Result<()> {
+    let project_structure = structure_lines(entries, omitted_per_dir, options);
+    let mut writer = PositionWriter { inner: writer, position: 0 };
+
+    writeln!(writer, "Project Structure:")?;
+    writeln!(writer, "{}", project_structure.join("\n"))?;
+    if options.structure_only {
+        return Ok(());
+    }
+
+    writeln!(writer, "\nFile Contents:")?;
+    let mut current_dir = None;
+    for (index, entry) in entries.iter().filter(|entry| entry.has_content()).enumerate() {
+        if index > 0 {
+            writeln!(writer)?;
+        }
+        if let Some(heading) = dir_heading(entry, &mut current_dir, options) {
+            write!(writer, "{}\n\n", heading)?;
+        }
+        if options.number_files {
+            writeln!(writer, "### File {}: {}", index + 1, entry.path)?;
+        }
+        write_block(&mut writer, entry, text_block_parts(entry, options), offsets)?;
+    }
+
+    Ok(())
+}
+
+/// Write the oneline format: a `path: first line` line per file, in structure order
+pub(crate) fn write_oneline(writer: &mut dyn Write, entries: &[FileEntry]) -> Result<()> {
+    for entry in entries {
+        writeln!(writer, "{}", oneline(entry))?;
+    }
+    Ok(())
+}
+
+/// An entry's line in the oneline format; entries without content show their structure note instead
+fn oneline(entry: &FileEntry) -> String {
+    match structure_suffix(entry) {
+        Some(suffix) => format!("{}{}", entry.path, suffix),
+        None if entry.has_content() && !entry.content.is_empty() => format!("{}: {}", entry.path, entry.content),
+        None if entry.has_content() => format!("{}:", entry.path),
+        None => entry.path.clone(),
+    }
+}
+
+/// With `--summary-header`, the block that opens the output: the languages of the included files by share
+/// of file count and of content size (which is what tokens are estimated from), largest first.
+/// `None` for formats without a summary or when no file has content.
+pub(crate) fn summary_header(entries: &[FileEntry], format: OutputFormat) -> Option<String> {
+    let mut per_language: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| entry.has_content()) {
+        let (files, chars) = per_language.entry(language_name(&entry.path)).or_default();
+        *files += 1;
+        *chars += entry.content.chars().count();
+    }
+    if per_language.is_empty() {
+        return None;
+    }
+
+    let total_files: usize = per_language.values().map(|(files, _)| files).sum();
+    let total_chars: usize = per_language.values().map(|(_, chars)| chars).sum();
+    let by_files = language_shares(&per_language, total_files, |(files, _)| *files);
+    let by_tokens = language_shares(&per_language, total_chars, |(_, chars)| *chars);
+
+    match format {
+        OutputFormat::Text => Some(format!(
+            "Project Summary:\nLanguages by files: {}\nLanguages by tokens: {}\n\n",
+            by_files, by_tokens
+        )),
+        OutputFormat::Markdown => Some(format!(
+            "# Project Summary\n\n- Languages by files: {}\n- Languages by tokens: {}\n\n",
+            by_files, by_tokens
+        )),
+        OutputFormat::Json | OutputFormat::Repomix | OutputFormat::Html | OutputFormat::Oneline => None,
+    }
+}
+
+/// Languages with their percentage of `total`, largest first, e.g. `Rust 60%, Markdown 25%, TOML 15%`
+fn language_shares(
+    per_language: &BTreeMap<&str, (usize, usize)>,
+    total: usize,
+    measure: impl Fn(&(usize, usize)) -> usize,
+) -> String {
+    let mut shares: Vec<(&str, usize)> = per_language.iter().map(|(language, counts)| (*language, measure(counts))).collect();
+    shares.sort_by(|(a_language, a), (b_language, b)| b.cmp(a).then_with(|| a_language.cmp(b_language)));
+
+    shares
+        .iter()
+        .map(|(language, amount)| {
+            let percentage = (*amount as f64 * 100.0 / total.max(1) as f64).round();
+            if percentage < 1.0 {
+                format!("{} <1%", language)
+            } else {
+                format!("{} {}%", language, percentage)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A file's path followed by its fenced content.
+///
+/// The block is `path:`, the fence, the content, a newline and the fence again. Because the fence is
+/// longer than any backtick run in the content, the content can be recovered byte for byte.
+/// With `--no-fence` the block is just `path:`, the content and a newline; blocks are separated by a blank line.
+fn text_block(entry: &FileEntry, options: &ProcessingOptions) -> String {
+    let (opening, closing) = text_block_parts(entry, options);
+    format!("{}{}{}", opening, entry.content, closing)
+}
+
+/// What comes before and after the content in a file's text block
+fn text_block_parts(entry: &FileEntry, options: &ProcessingOptions) -> (String, String) {
+    let labels = header_labels(entry);
+    if options.no_fence {
+        let newline = if entry.content.ends_with('\n') { "" } else { "\n" };
+        return (format!("{}{}:\n", entry.path, labels), newline.to_string());
+    }
+    let fence = fence_for(&entry.content, options.fence_char);
+    (format!("{}{}:\n{}\n", entry.path, labels, fence), format!("\n{}\n", fence))
+}
+
+/// Header note of a file that couldn't be read and whose block is left empty
+pub(crate) const READ_ERROR_LABEL: &str = " [read error]";
+
+/// The notes after a file's path in its header: its permission bits with `--show-permissions`, e.g.
+/// ` [mode: 755]`, then its summary with `--file-summaries`, e.g. ` [12 fns, 3 structs]`, and
+/// ` [read error]` for a file left empty because it couldn't be read
+pub(crate) fn header_labels(entry: &FileEntry) -> String {
+    let part = entry.part.map(|(index, count)| format!(" (part {}/{})", index, count)).unwrap_or_default();
+    let mode = entry.mode.map(|mode| format!(" [mode: {:o}]", mode)).unwrap_or_default();
+    let summary = entry.summary.as_ref().map(|summary| format!(" [{}]", summary)).unwrap_or_default();
+    let read_error = if entry.read_error { READ_ERROR_LABEL } else { "" };
+    part + &mode + &summary + read_error
+}
+
+/// With `--group-by-dir`, the heading to write before `entry` when it starts a new directory group.
+/// Files at the project root are grouped under `.`.
+fn dir_heading<'a>(entry: &'a FileEntry, current_dir: &mut Option<&'a str>, options: &ProcessingOptions) -> Option<String> {
+    let template = options.dir_heading_template.as_ref()?;
+    let dir = parent_dir(&entry.path);
+    if *current_dir == Some(dir) {
+        return None;
+    }
+    *current_dir = Some(dir);
+    Some(template.replace("{dir}", if dir.is_empty() { "." } else { dir }))
+}
+
+/// A fence of backticks or tildes longer than any run of that character in the content (at least three)
+pub(crate) fn fence_for(content: &str, fence_char: FenceChar) -> String {
+    let fence_char = fence_char.as_char();
+    let longest_run = content
+        .split(|c| c != fence_char)
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    fence_char.to_string().repeat(longest_run.max(2) + 1)
+}
+
+/// Lines of the structure listing, either flat paths or an indented tree, capped by `max_structure_entries`
+pub(crate) fn structure_lines(
+    entries: &[FileEntry],
+    omitted_per_dir: &BTreeMap<String, usize>,
+    options: &ProcessingOptions,
+) -> Vec<String> {
+    let listed: Vec<(&FileEntry, Option<String>)> = entries
+        .iter()
+        .zip(structure_suffixes(entries, options))
+        .filter(|(entry, _)| !is_continuation(entry))
+        .collect();
+    if options.tree {
+        let labelled = listed.into_iter().map(|(entry, suffix)| (entry.path.as_str(), suffix));
+        return render_tree(labelled, omitted_per_dir, options.max_structure_entries, options.compact_dirs);
+    }
+
+    let mut project_structure = Vec::new();
+
+    // Note omitted files after the last kept entry of each capped directory
+    let mut last_index_per_dir: HashMap<&str, usize> = HashMap::new();
+    for (index, (entry, _)) in listed.iter().enumerate() {
+        last_index_per_dir.insert(parent_dir(&entry.path), index);
+    }
+
+    let listed_count = listed.len();
+    for (index, (entry, suffix)) in listed.into_iter().enumerate() {
+        if options.max_structure_entries == Some(index) {
+            project_structure.push(format!("(+ {} more files)", listed_count - index));
+            break;
+        }
+        match suffix {
+            Some(suffix) => project_structure.push(format!("{}{}", entry.path, suffix)),
+            None => project_structure.push(entry.path.clone()),
+        }
+
+        let dir = parent_dir(&entry.path);
+        if let Some(omitted) = omitted_per_dir.get(dir) {
+            if last_index_per_dir.get(dir) == Some(&index) {
+                let dir_display = if dir.is_empty() { "." } else { dir };
+                project_structure.push(format!("{}/ ({} more files in this directory omitted)", dir_display, omitted));
+            }
+        }
+    }
+
+    project_structure
+}
+
+/// Text shown after each entry's path in the structure: its symlink target, why it was skipped,
+/// its number when files are numbered, or into how many parts it was split
+fn structure_suffixes(entries: &[FileEntry], options: &ProcessingOptions) -> Vec<Option<String>> {
+    let mut number = 0;
+    entries
+        .iter()
+        .map(|entry| {
+            if !entry.has_content() {
+                return structure_suffix(entry);
+            }
+            number += 1;
+            let number = (options.number_files && !options.structure_only).then(|| format!(" [{}]", number));
+            let parts = entry.part.map(|(_, count)| format!(" ({} parts)", count));
+            match (number, parts) {
+                (Some(number), Some(parts)) => Some(number + &parts),
+                (number, parts) => number.or(parts),
+            }
+        })
+        .collect()
+}
+
+/// Whether an entry continues a file split into parts, which the structure lists only once
+fn is_continuation(entry: &FileEntry) -> bool {
+    entry.part.is_some_and(|(index, _)| index > 1)
+}
+
+/// Text shown after an entry's path in the structure: its symlink target, why it was skipped or that
+/// it is only listed
+pub(crate) fn structure_suffix(entry: &FileEntry) -> Option<String> {
+    if let Some(target) = &entry.link_target {
+        return Some(format!(" -> {}", target));
+    }
+    if entry.orientation {
+        return Some(String::from(" (listed for orientation)"));
+    }
+    if let Some(description) = &entry.image {
+        return Some(format!(" [image: {}]", description));
+    }
+    entry.skip_reason.as_ref().map(|reason| format!(" (skipped: {})", reason))
+}
+
+/// Top-level JSON document
+#[derive(Serialize)]
+struct JsonContext<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generated_at: Option<String>,
+    structure: Vec<&'a str>,
+    /// The structure as a nested tree, with `--tree`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tree: Option<JsonTreeNode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<JsonFile<'a>>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<JsonSkippedFile<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    symlinks: Vec<JsonSymlink<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<JsonImage<'a>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    omitted_per_dir: &'a BTreeMap<String, usize>,
+}
+
+/// A directory or file in the JSON tree
+#[derive(Serialize)]
+struct JsonTreeNode {
+    name: String,
+    /// `directory` or `file`
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// Entries of a directory, in the order of the structure listing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<JsonTreeNode>>,
+    /// Number of files left out of a directory by `--max-files-per-dir`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    omitted: Option<usize>,
+}
+
+impl JsonTreeNode {
+    /// The tree of the listed entries, under a `.` directory
+    fn root(entries: &[FileEntry], omitted_per_dir: &BTreeMap<String, usize>) -> JsonTreeNode {
+        let root = TreeNode::from_paths(entries.iter().map(|entry| (entry.path.as_str(), None)), omitted_per_dir);
+        let mut node = JsonTreeNode::from_tree(".", &root);
+        // The root is a directory even when nothing was found
+        node.kind = "directory";
+        node.children.get_or_insert_with(Vec::new);
+        node
+    }
+    
+    fn from_tree(name: &str, node: &TreeNode) -> JsonTreeNode {
+        if !node.is_dir() {
+            return JsonTreeNode { name: name.to_string(), kind: "file", children: None, omitted: None };
+        }
+        JsonTreeNode {
+            name: name.to_string(),
+            kind: "directory",
+            children: Some(node.children.iter().map(|(name, child)| JsonTreeNode::from_tree(name, child)).collect()),
+            omitted: (node.omitted > 0).then_some(node.omitted),
+        }
+    }
+}
+
+/// A single file in the JSON document
+#[derive(Serialize)]
+struct JsonFile<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number: Option<usize>,
+    path: &'a str,
+    /// Permission bits in octal, e.g. `755`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    /// One-line description, e.g. `12 fns, 3 structs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<&'a str>,
+    /// SHA-256 of the file as read, for telling which files changed between two contexts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<&'a str>,
+    /// Which part of a file split by `--chunk-large-files` the content is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    part: Option<JsonPart>,
+    content: &'a str,
+}
+
+/// A part of a file split into several, e.g. part 1 of 3
+#[derive(Serialize)]
+struct JsonPart {
+    index: usize,
+    count: usize,
+}
+
+/// A file listed in the structure whose content was left out
+#[derive(Serialize)]
+struct JsonSkippedFile<'a> {
+    path: &'a str,
+    reason: &'a str,
+}
+
+/// A symlink listed in the structure with its target
+#[derive(Serialize)]
+struct JsonSymlink<'a> {
+    path: &'a str,
+    target: &'a str,
+}
+
+/// An image listed in the structure with its format and dimensions
+#[derive(Serialize)]
+struct JsonImage<'a> {
+    path: &'a str,
+    description: &'a str,
+}
+
+/// Write the JSON format, compact by default or indented when `json_pretty` is set
+pub(crate) fn write_json(
+    writer: &mut dyn Write,
+    entries: &[FileEntry],
+    omitted_per_dir: &BTreeMap<String, usize>,
+    options: &ProcessingOptions,
+) -> Result<()> {
+    let files = entries
+        .iter()
+        .filter(|entry| entry.has_content())
+        .enumerate()
+        .map(|(index, entry)| JsonFile {
+            number: options.number_files.then_some(index + 1),
+            path: &entry.path,
+            mode: entry.mode.map(|mode| format!("{:o}", mode)),
+            summary: entry.summary.as_deref(),
+            hash: entry.hash.as_deref(),
+            part: entry.part.map(|(index, count)| JsonPart { index, count }),
+            content: &entry.content,
+        })
+        .collect();
+    let skipped = entries
+        .iter()
+        .filter_map(|entry| {
+            let reason = entry.skip_reason.as_deref()?;
+            Some(JsonSkippedFile { path: &entry.path, reason })
+        })
+        .collect();
+    let symlinks = entries
+        .iter()
+        .filter_map(|entry| {
+            let target = entry.link_target.as_deref()?;
+            Some(JsonSymlink { path: &entry.path, target })
+        })
+        .collect();
+    let images = entries
+        .iter()
+        .filter_map(|entry| {
+            let description = entry.image.as_deref()?;
+            Some(JsonImage { path: &entry.path, description })
+        })
+        .collect();
+    let context = JsonContext {
+        generated_at: generation_timestamp(options.no_timestamps || !options.json_timestamp).map(format_utc_timestamp),
+        structure: entries.iter().filter(|entry| !is_continuation(entry)).map(|entry| entry.path.as_str()).collect(),
+        tree: options.tree.then(|| JsonTreeNode::root(entries, omitted_per_dir)),
+        files: (!options.structure_only).then_some(files),
+        skipped,
+        symlinks,
+        images,
+        omitted_per_dir,
+    };
+
+    if options.json_pretty {
+        serde_json::to_writer_pretty(&mut *writer, &context)?;
+    } else {
+        serde_json::to_writer(&mut *writer, &context)?;
+    }
+    writeln!(writer)?;
+
+    Ok(())
+}
+
+/// Write the markdown format: the structure in a code block, then a heading and fenced block per file
+pub(crate) fn write_markdown(
+    writer: &mut dyn Write,
+    entries: &[FileEntry],
+    omitted_per_dir: &BTreeMap<String, usize>,
+    options: &ProcessingOptions,
+    offsets: &mut Vec<BlockOffset>,
+) -> Result<()> {
+    let project_structure = structure_lines(entries, omitted_per_dir, options);
+    let mut writer = PositionWriter { inner: writer, position: 0 };
+
+    writeln!(writer, "# Project Structure\n")?;
+    writeln!(writer, "```\n{}\n```", project_structure.join("\n"))?;
+    if options.structure_only {
+        return Ok(());
+    }
+
+    writeln!(writer, "\n# File Contents")?;
+    let mut current_dir = None;
+    for (index, entry) in entries.iter().filter(|entry| entry.has_content()).enumerate() {
+        if let Some(heading) = dir_heading(entry, &mut current_dir, options) {
+            write!(writer, "\n{}\n", heading)?;
+        }
+        let number = options.number_files.then_some(index + 1);
+        writeln!(writer)?;
+        write_block(&mut writer, entry, markdown_block_parts(entry, number, options), offsets)?;
+    }
+
+    Ok(())
+}
+
+/// Separator around repomix section headings
+const REPOMIX_SECTION_SEPARATOR: &str = "================================================================";
+
+/// Separator around each file path in the repomix format
+const REPOMIX_FILE_SEPARATOR: &str = "================";
+
+/// Summary header of the repomix plain-text format
+const REPOMIX_SUMMARY: &str = "\
+This file is a merged representation of the entire codebase, combined into a single document.
+
+================================================================
+File Summary
+================================================================
+
+Purpose:
+--------
+This file contains a packed representation of the entire repository's contents.
+It is designed to be easily consumable by AI systems for analysis, code review,
+or other automated processes.
+
+File Format:
+------------
+The content is organized as follows:
+1. This summary section
+2. Directory structure
+3. Multiple file entries, each consisting of:
+  a. A separator line (================)
+  b. The file path (File: path/to/file)
+  c. Another separator line
+  d. The full contents of the file
+  e. A blank line
+
+Usage Guidelines:
+-----------------
+- This file should be treated as read-only. Any changes should be made to the
+  original repository files, not this packed version.
+- When processing this file, use the file path to distinguish
+  between different files in the repository.
+
+Notes:
+------
+- Some files may have been excluded based on blacklist, whitelist and .gitignore rules
+- Binary files are not included in this packed representation
+
+";
+
+/// Write the plain-text layout of repomix, so pipelines that parse its delimiters can consume the output
+pub(crate) fn write_repomix(
+    writer: &mut dyn Write,
+    entries: &[FileEntry],
+    options: &ProcessingOptions,
+) -> Result<()> {
+    write!(writer, "{}", REPOMIX_SUMMARY)?;
+
+    writeln!(writer, "{}\nDirectory Structure\n{}", REPOMIX_SECTION_SEPARATOR, REPOMIX_SECTION_SEPARATOR)?;
+    for line in render_indented(entries.iter().map(|entry| entry.path.as_str())) {
+        writeln!(writer, "{}", line)?;
+    }
+
+    if !options.structure_only {
+        writeln!(writer, "\n{}\nFiles\n{}\n", REPOMIX_SECTION_SEPARATOR, REPOMIX_SECTION_SEPARATOR)?;
+        for entry in entries.iter().filter(|entry| entry.has_content()) {
+            writeln!(writer, "{}\nFile: {}{}\n{}", REPOMIX_FILE_SEPARATOR, entry.path, header_labels(entry), REPOMIX_FILE_SEPARATOR)?;
+            writeln!(writer, "{}\n", entry.content)?;
+        }
+    }
+
+    writeln!(writer, "{}\nEnd of Codebase\n{}", REPOMIX_SECTION_SEPARATOR, REPOMIX_SECTION_SEPARATOR)?;
+
+    Ok(())
+}
+
+/// A file's heading, optionally numbered and linking to the file
+fn markdown_heading(entry: &FileEntry, number: Option<usize>, options: &ProcessingOptions) -> String {
+    let label = match number {
+        Some(number) => format!("File {}: ", number),
+        None => String::new(),
+    };
+    if options.file_links {
+        format!("## {}[{}]({}){}", label, entry.path, markdown_link_target(&entry.path), header_labels(entry))
+    } else {
+        format!("## {}{}{}", label, entry.path, header_labels(entry))
+    }
+}
+
+/// The language of a file as named by its extension (empty when it has none), e.g. `rs` for `src/main.rs`
+pub(crate) fn file_language(path: &str) -> &str {
+    path.rsplit_once('.').map(|(_, ext)| ext).filter(|ext| !ext.contains('/')).unwrap_or("")
+}
+
+/// A file's heading and its content in a fence that is longer than any backtick run inside it
+fn markdown_block(entry: &FileEntry, number: Option<usize>, options: &ProcessingOptions) -> String {
+    let (opening, closing) = markdown_block_parts(entry, number, options);
+    format!("{}{}{}", opening, entry.content, closing)
+}
+
+/// What comes before and after the content in a file's markdown block
+fn markdown_block_parts(entry: &FileEntry, number: Option<usize>, options: &ProcessingOptions) -> (String, String) {
+    let fence = fence_for(&entry.content, options.fence_char);
+    let language = file_language(&entry.path);
+    (
+        format!("{}\n\n{}{}\n", markdown_heading(entry, number, options), fence, language),
+        format!("\n{}\n", fence),
+    )
+}
+
+/// Percent-encode the characters that would end or break a markdown link destination
+fn markdown_link_target(path: &str) -> String {
+    let mut target = String::new();
+    for c in path.chars() {
+        match c {
+            ' ' => target.push_str("%20"),
+            '(' => target.push_str("%28"),
+            ')' => target.push_str("%29"),
+            '<' => target.push_str("%3C"),
+            '>' => target.push_str("%3E"),
+            _ => target.push(c),
+        }
+    }
+    target
+}
+
+/// A single file of a `serve` stream in the JSON format
+#[derive(Serialize)]
+struct JsonStreamEntry<'a> {
+    path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipped: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<&'a str>,
+}
+
+/// Write one file of a `serve` stream: a text block (or a structure line for files without content),
+/// or a single line of JSON
+pub(crate) fn write_stream_entry(writer: &mut dyn Write, entry: &FileEntry, options: &ProcessingOptions) -> Result<()> {
+    match options.format {
+        OutputFormat::Text => match structure_suffix(entry) {
+            Some(suffix) => writeln!(writer, "{}{}", entry.path, suffix)?,
+            None => write!(writer, "{}", text_block(entry, options))?,
+        },
+        OutputFormat::Repomix => match structure_suffix(entry) {
+            Some(suffix) => writeln!(writer, "{}{}", entry.path, suffix)?,
+            None => write!(
+                writer,
+                "{}\nFile: {}\n{}\n{}\n\n",
+                REPOMIX_FILE_SEPARATOR, entry.path, REPOMIX_FILE_SEPARATOR, entry.content
+            )?,
+        },
+        OutputFormat::Markdown => match structure_suffix(entry) {
+            Some(suffix) => writeln!(writer, "{}{}\n", markdown_heading(entry, None, options), suffix)?,
+            None => write!(writer, "{}", markdown_block(entry, None, options))?,
+        },
+        OutputFormat::Html => anyhow::bail!("The HTML format is a single page and cannot be streamed"),
+        OutputFormat::Oneline => writeln!(writer, "{}", oneline(entry))?,
+        OutputFormat::Json => {
+            let line = JsonStreamEntry {
+                path: &entry.path,
+                hash: entry.hash.as_deref(),
+                content: entry.has_content().then_some(entry.content.as_str()),
+                skipped: entry.skip_reason.as_deref(),
+                target: entry.link_target.as_deref(),
+            };
+            serde_json::to_writer(&mut *writer, &line)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+    
+    Ok(())
+}
@@ -0,0 +1,290 @@
+//! Parsing of the plain-text output format back into its parts
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+
+use crate::output::READ_ERROR_LABEL;
+use crate::summary::is_summary_label;
+
+/// The parts of a context file in the plain-text format
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedContext {
+    /// Lines of the "Project Structure" section
+    pub structure: Vec<String>,
+    /// `(path, content)` pairs of the "File Contents" section, in output order
+    pub files: Vec<(String, String)>,
+}
+
+const STRUCTURE_HEADER: &str = "Project Structure:\n";
+const CONTENTS_HEADER: &str = "\nFile Contents:\n";
+
+/// Parse the plain-text output of `save_project_structure_and_files` back into the structure
+/// listing and the original file contents, byte for byte.
+///
+/// A trailing `--stamp-hash` line is ignored.
+pub fn parse_context(input: &str) -> Result<ParsedContext> {
+    let body = input
+        .strip_prefix(STRUCTURE_HEADER)
+        .context("Not a contextify context: missing 'Project Structure:' header")?;
+
+    // Structure-only output has no contents section
+    let (structure, mut rest) = match body.find(CONTENTS_HEADER) {
+        Some(index) => (&body[..index], &body[index + CONTENTS_HEADER.len()..]),
+        None => (body.strip_suffix('\n').unwrap_or(body), ""),
+    };
+
+    let mut parsed = ParsedContext {
+        structure: structure.split('\n').filter(|line| !line.is_empty()).map(String::from).collect(),
+        files: Vec::new(),
+    };
+
+    while !rest.is_empty() && !rest.starts_with("<!-- contextify-hash: ") {
+        // Only computed for error messages
+        let offset = input.len() - rest.len();
+        let line_number = || input[..offset].matches('\n').count() + 1;
+
+        let (path_line, after_path) = rest
+            .split_once('\n')
+            .with_context(|| format!("Line {}: expected a 'path:' header", line_number()))?;
+        let (path, part) = path_line
+            .strip_suffix(':')
+            .map(|header| split_part_label(strip_header_labels(header)))
+            .with_context(|| format!("Line {}: expected a 'path:' header, found '{}'", line_number(), path_line))?;
+
+        let (fence, after_fence) = after_path
+            .split_once('\n')
+            .with_context(|| format!("Line {}: missing opening fence for {}", line_number() + 1, path))?;
+        if fence.len() < 3 || !(fence.chars().all(|c| c == '`') || fence.chars().all(|c| c == '~')) {
+            anyhow::bail!("Line {}: expected an opening fence for {}, found '{}'", line_number() + 1, path, fence);
+        }
+
+        // The content ends at the first line that is exactly the fence
+        let closing = format!("\n{}\n", fence);
+        let (content, after_block) = if let Some(after_block) = after_fence.strip_prefix(&closing[1..]) {
+            ("", after_block)
+        } else {
+            let content_end = after_fence
+                .find(&closing)
+                .with_context(|| format!("Line {}: unterminated fence for {}", line_number() + 1, path))?;
+            (&after_fence[..content_end], &after_fence[content_end + closing.len()..])
+        };
+
+        push_file(&mut parsed.files, path, part, content.to_string());
+
+        // Skip the blank line that separates blocks
+        rest = after_block.strip_prefix('\n').unwrap_or(after_block);
+    }
+
+    Ok(parsed)
+}
+
+/// Extract `(path, content)` pairs from a context file in the plain-text format.
+///
+/// Unlike [`parse_context`], this is lenient about the edits a person or an LLM tends to make: Windows line
+/// endings, a missing structure section, language tags on the opening fence, trailing whitespace after
+/// fences and missing blank lines between blocks. Text outside file blocks is ignored. For unedited output
+/// the contents are returned byte for byte.
+pub fn extract_files(input: &str) -> Result<Vec<(String, String)>> {
+    let normalized = input.replace("\r\n", "\n");
+    let lines: Vec<&str> = normalized.split('\n').collect();
+
+    // Blocks start after the contents header when there is one
+    let mut index = lines
+        .iter()
+        .position(|line| line.trim_end() == "File Contents:")
+        .map_or(0, |header| header + 1);
+
+    let mut files = Vec::new();
+    while index + 1 < lines.len() {
+        let Some(fence) = block_start(lines[index], lines[index + 1]) else {
+            index += 1;
+            continue;
+        };
+        let (path, part) = split_part_label(strip_header_labels(lines[index].trim_end().strip_suffix(':').unwrap_or_default().trim()));
+
+        let content_start = index + 2;
+        let content_end = (content_start..lines.len())
+            .find(|&line| lines[line].trim_end() == fence)
+            .with_context(|| format!("Line {}: unterminated fence for {}", index + 2, path))?;
+
+        push_file(&mut files, path, part, lines[content_start..content_end].join("\n"));
+        index = content_end + 1;
+    }
+
+    Ok(files)
+}
+
+/// A structural problem in a context file, found by [`validate_context`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextIssue {
+    /// 1-based line of the context file the problem is at
+    pub line: usize,
+    pub message: String,
+}
+
+/// Check a context file in the fenced plain-text format for corruption from transforms or manual edits:
+/// that every fence is closed, that no fence stands outside a file block, that no file has two blocks, and
+/// that the structure listing and the content blocks name the same files. Files the structure lists
+/// without content (skipped, symlinks, images, orientation-only entries) need no block, and the two are
+/// only cross-checked for a flat, uncapped listing. An empty list means the file is well-formed.
+pub fn validate_context(input: &str) -> Vec<ContextIssue> {
+    let lines: Vec<&str> = input.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+    let issue = |index: usize, message: String| ContextIssue { line: index + 1, message };
+
+    let Some(structure_start) = lines.iter().position(|line| *line == "Project Structure:") else {
+        return vec![issue(0, String::from("missing 'Project Structure:' header"))];
+    };
+    let contents_start = lines.iter().skip(structure_start).position(|line| *line == "File Contents:").map(|index| index + structure_start);
+    let mut issues = Vec::new();
+
+    // Content blocks, by path with the line of their first block
+    let mut blocks: HashMap<&str, usize> = HashMap::new();
+    let mut previous: Option<&str> = None;
+    let mut index = contents_start.map_or(lines.len(), |start| start + 1);
+    while index < lines.len() {
+        let fence = match lines.get(index + 1).and_then(|next| block_start(lines[index], next)) {
+            Some(fence) => fence,
+            None => {
+                if fence_of(lines[index]).is_some() {
+                    issues.push(issue(index, format!("fence '{}' outside a file block", lines[index].trim_end())));
+                }
+                index += 1;
+                continue;
+            }
+        };
+        let (path, part) = split_part_label(strip_header_labels(lines[index].trim_end().strip_suffix(':').unwrap_or_default().trim()));
+        let continues_previous = part.is_some_and(|part| part > 1) && previous == Some(path);
+        match blocks.get(path) {
+            Some(first) if !continues_previous => {
+                issues.push(issue(index, format!("{} appears twice (first at line {})", path, first + 1)));
+            }
+            Some(_) => {}
+            None => {
+                blocks.insert(path, index);
+            }
+        }
+        previous = Some(path);
+
+        let Some(closing) = (index + 2..lines.len()).find(|&line| lines[line].trim_end() == fence) else {
+            issues.push(issue(index + 1, format!("unterminated fence for {}", path)));
+            break;
+        };
+        index = closing + 1;
+    }
+
+    // Only a flat listing of every file can be matched against the blocks
+    let structure = &lines[structure_start + 1..contents_start.unwrap_or(lines.len())];
+    let is_tree = structure.iter().any(|line| line.contains("├── ") || line.contains("└── "));
+    let is_capped = structure.iter().any(|line| line.starts_with("(+ ") && line.ends_with(" more files)"));
+    if contents_start.is_none() || is_tree {
+        return issues;
+    }
+
+    let mut listed = HashSet::new();
+    for (offset, line) in structure.iter().enumerate() {
+        let Some(path) = listed_path(line) else {
+            continue;
+        };
+        listed.insert(path);
+        if !blocks.contains_key(path) {
+            issues.push(issue(structure_start + 1 + offset, format!("{} is listed in the structure but has no content block", path)));
+        }
+    }
+    if !is_capped {
+        let mut unlisted: Vec<(&str, usize)> = blocks.into_iter().filter(|(path, _)| !listed.contains(path)).collect();
+        unlisted.sort_by_key(|(_, index)| *index);
+        issues.extend(unlisted.into_iter().map(|(path, index)| issue(index, format!("{} has a content block but isn't listed in the structure", path))));
+    }
+
+    issues.sort_by_key(|issue| issue.line);
+    issues
+}
+
+/// The path of a structure line for a file that should have a content block, without its `--number-files`
+/// number and `--chunk-large-files` part count
+fn listed_path(line: &str) -> Option<&str> {
+    const WITHOUT_CONTENT: [&str; 4] = [" -> ", " (listed for orientation)", " [image: ", " (skipped: "];
+    if line.is_empty() || line.ends_with(" more files in this directory omitted)") || WITHOUT_CONTENT.iter().any(|note| line.contains(note)) {
+        return None;
+    }
+    let path = line
+        .strip_suffix(" parts)")
+        .and_then(|rest| rest.rsplit_once(" ("))
+        .filter(|(_, count)| !count.is_empty() && count.chars().all(|c| c.is_ascii_digit()))
+        .map_or(line, |(path, _)| path);
+    let path = path
+        .strip_suffix(']')
+        .and_then(|rest| rest.rsplit_once(" ["))
+        .filter(|(_, number)| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+        .map_or(path, |(path, _)| path);
+    Some(path)
+}
+
+/// A header's path without the notes that `--show-permissions` (` [mode: 755]`), `--file-summaries`
+/// (` [12 fns, 3 structs]`) and `--on-read-error empty` (` [read error]`) add
+fn strip_header_labels(header: &str) -> &str {
+    let header = header.strip_suffix(READ_ERROR_LABEL).unwrap_or(header);
+    let header = header
+        .strip_suffix(']')
+        .and_then(|rest| rest.rsplit_once(" ["))
+        .filter(|(_, label)| is_summary_label(label))
+        .map_or(header, |(path, _)| path);
+    header
+        .strip_suffix(']')
+        .and_then(|rest| rest.rsplit_once(" [mode: "))
+        .filter(|(_, mode)| !mode.is_empty() && mode.chars().all(|c| c.is_digit(8)))
+        .map_or(header, |(path, _)| path)
+}
+
+/// Paths of the blocks whose header notes that the file couldn't be read (`--on-read-error empty`)
+pub(crate) fn read_error_paths(input: &str) -> HashSet<String> {
+    input
+        .lines()
+        .filter_map(|line| line.trim_end().strip_suffix(':')?.strip_suffix(READ_ERROR_LABEL))
+        .map(|header| split_part_label(strip_header_labels(header.trim())).0.to_string())
+        .collect()
+}
+
+/// A header's path and, for a part of a file split by `--chunk-large-files`, the number from its
+/// ` (part 2/3)` label
+fn split_part_label(header: &str) -> (&str, Option<usize>) {
+    header
+        .strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once(" (part "))
+        .and_then(|(path, label)| {
+            let (index, count) = label.split_once('/')?;
+            count.parse::<usize>().ok()?;
+            Some((path, Some(index.parse().ok()?)))
+        })
+        .unwrap_or((header, None))
+}
+
+/// Add a file block, joining a later part of a split file onto the part before it
+fn push_file(files: &mut Vec<(String, String)>, path: &str, part: Option<usize>, content: String) {
+    if part.is_some_and(|index| index > 1) {
+        if let Some((_, previous)) = files.last_mut().filter(|(previous_path, _)| previous_path == path) {
+            previous.push_str(&content);
+            return;
+        }
+    }
+    files.push((path.to_string(), content));
+}
+
+/// If the lines open a file block (`path:` followed by a fence), the fence's backticks or tildes
+fn block_start<'a>(header: &str, fence_line: &'a str) -> Option<&'a str> {
+    let path = header.trim_end().strip_suffix(':')?;
+    if path.trim().is_empty() {
+        return None;
+    }
+
+    fence_of(fence_line)
+}
+
+/// If the line starts with a fence (three or more backticks or tildes), the fence
+fn fence_of(line: &str) -> Option<&str> {
+    let line = line.trim_end();
+    let fence_char = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let fence_length = line.len() - line.trim_start_matches(fence_char).len();
+    (fence_length >= 3).then(|| &line[..fence_length])
+}
@@ -0,0 +1,92 @@
+//! Exclusions tailored to a project's primary language, detected from a histogram of its file extensions
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::is_vcs_dir;
+use crate::language::{language_extensions, language_name};
+
+/// Build outputs, dependencies and caches of each language, excluded when it is the primary one
+const PRESETS: &[(&str, &[&str])] = &[
+    ("Rust", &["target/"]),
+    ("Python", &["__pycache__/", "*.pyc", ".venv/", "venv/", ".pytest_cache/", ".mypy_cache/", ".tox/"]),
+    ("TypeScript", &["node_modules/", "dist/", "coverage/", ".next/"]),
+    ("JavaScript", &["node_modules/", "dist/", "coverage/", ".next/"]),
+    ("Go", &["vendor/"]),
+    ("Java", &["target/", "build/", ".gradle/", "*.class"]),
+    ("Kotlin", &["build/", ".gradle/"]),
+    ("C#", &["bin/", "obj/"]),
+    ("Ruby", &["vendor/bundle/", ".bundle/"]),
+    ("PHP", &["vendor/"]),
+    ("Dart", &[".dart_tool/", "build/"]),
+    ("Elixir", &["_build/", "deps/"]),
+    ("Swift", &[".build/"]),
+];
+
+/// Files counted at most, which is plenty to tell the primary language and keeps huge trees quick
+const MAX_COUNTED_FILES: usize = 20_000;
+
+/// A project's primary language and the patterns excluded for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguagePreset {
+    pub language: &'static str,
+    pub patterns: &'static [&'static str],
+}
+
+impl LanguagePreset {
+    /// Whitelist patterns matching the language's source files, e.g. `*.py` and `*.pyi` for Python
+    pub fn source_patterns(&self) -> Vec<String> {
+        language_extensions(self.language).map(|extension| format!("*.{}", extension)).collect()
+    }
+}
+
+/// The presets of every language that has one, in the order [`detect_language_preset`] breaks ties in
+pub fn language_presets() -> impl Iterator<Item = LanguagePreset> {
+    PRESETS.iter().map(|(language, patterns)| LanguagePreset { language, patterns })
+}
+
+/// The preset of the language with the most files under the input paths (resolved against `root`), or
+/// `None` when no language with a preset is found. Ties go to the language listed first in [`PRESETS`].
+///
+/// The scan happens before any filtering, but doesn't walk into version control metadata or into the
+/// directories that some preset excludes, so that e.g. `node_modules/` can't make a Python project look
+/// like a JavaScript one.
+pub fn detect_language_preset(root: &Path, paths: &[PathBuf]) -> Option<LanguagePreset> {
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+    let files = paths
+        .iter()
+        .flat_map(|path| WalkDir::new(root.join(path)).into_iter().filter_entry(|entry| entry.depth() == 0 || !is_pruned(entry)))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .take(MAX_COUNTED_FILES);
+    for entry in files {
+        let language = language_name(&entry.file_name().to_string_lossy());
+        if PRESETS.iter().any(|(name, _)| *name == language) {
+            *counts.entry(language).or_default() += 1;
+        }
+    }
+
+    let mut best: Option<(&'static str, &'static [&'static str], usize)> = None;
+    for (language, patterns) in PRESETS {
+        let count = counts.get(language).copied().unwrap_or(0);
+        if count > 0 && best.is_none_or(|(_, _, best_count)| count > best_count) {
+            best = Some((language, patterns, count));
+        }
+    }
+    best.map(|(language, patterns, _)| LanguagePreset { language, patterns })
+}
+
+/// Whether the scan skips a directory: VCS metadata, or a plain directory name excluded by a preset
+fn is_pruned(entry: &walkdir::DirEntry) -> bool {
+    if !entry.file_type().is_dir() {
+        return false;
+    }
+    let name = entry.file_name().to_string_lossy();
+    is_vcs_dir(entry)
+        || PRESETS
+            .iter()
+            .flat_map(|(_, patterns)| patterns.iter())
+            .any(|pattern| pattern.strip_suffix('/').is_some_and(|dir| dir == name))
+}
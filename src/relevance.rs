@@ -0,0 +1,35 @@
+//! Heuristic relevance scoring of files against a free-text query
+
+/// Lowercased words of at least two characters from a query
+pub(crate) fn query_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|term| term.chars().count() >= 2)
+        .map(str::to_lowercase)
+        .collect();
+    terms.dedup();
+    terms
+}
+
+/// Score a file for the query terms: matches in the file name count most, then matches in the directory
+/// path, then how often the terms occur in the content relative to its length (a simple TF measure)
+pub(crate) fn relevance_score(path: &str, content: &str, terms: &[String]) -> f64 {
+    let path = path.to_lowercase();
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path.as_str()));
+    let content = content.to_lowercase();
+    let word_count = content.split_whitespace().count().max(1) as f64;
+
+    terms
+        .iter()
+        .map(|term| {
+            let mut score = 0.0;
+            if name.contains(term.as_str()) {
+                score += 10.0;
+            } else if dir.contains(term.as_str()) {
+                score += 3.0;
+            }
+            let occurrences = content.matches(term.as_str()).count() as f64;
+            score + occurrences / word_count.sqrt()
+        })
+        .sum()
+}
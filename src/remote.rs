@@ -0,0 +1,58 @@
+//! Pattern lists fetched over HTTP(S), cached in the user's cache directory (`remote-lists` feature)
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// How long a fetched list is used before it is downloaded again
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Timeout for the whole download
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The list at `url`, from the cache when it was fetched within the last day.
+/// Network and HTTP failures are errors; stale cache entries are not used in their place.
+pub(crate) fn fetch_list(url: &str) -> Result<String> {
+    let cache_path = cache_path(url);
+    if let Some(path) = &cache_path {
+        let fresh = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age < CACHE_TTL);
+        if fresh {
+            if let Ok(content) = fs::read_to_string(path) {
+                return Ok(content);
+            }
+        }
+    }
+
+    let content = ureq::get(url)
+        .timeout(FETCH_TIMEOUT)
+        .call()
+        .with_context(|| format!("Failed to fetch list from {}", url))?
+        .into_string()
+        .with_context(|| format!("Failed to read list from {}", url))?;
+
+    // Caching is an optimization; a read-only cache directory shouldn't fail the run
+    if let Some(path) = &cache_path {
+        let written = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(path, &content));
+        if let Err(e) = written {
+            eprintln!("Warning: Could not cache list from {} at {}: {}", url, path.display(), e);
+        }
+    }
+
+    Ok(content)
+}
+
+/// Cache file for a URL: `<cache dir>/contextify/lists/<hash of the URL>.txt`
+fn cache_path(url: &str) -> Option<PathBuf> {
+    let digest = format!("{:x}", Sha256::digest(url.as_bytes()));
+    let mut path = dirs::cache_dir()?;
+    path.push("contextify");
+    path.push("lists");
+    path.push(format!("{}.txt", &digest[..16]));
+    Some(path)
+}
@@ -0,0 +1,43 @@
+//! A language-agnostic outline of a file: the lines that declare something, without the bodies
+
+/// Modifiers that may precede a declaration keyword
+const MODIFIERS: &[&str] = &[
+    "pub", "pub(crate)", "pub(super)", "export", "default", "async", "unsafe", "extern", "const", "static",
+    "public", "private", "protected", "internal", "abstract", "final", "sealed", "override", "virtual",
+    "inline", "open", "data",
+];
+
+/// Keywords that start a declaration in common languages
+const DECLARATION_KEYWORDS: &[&str] = &[
+    "fn", "struct", "enum", "trait", "impl", "type", "mod", "macro_rules!", "union",
+    "class", "interface", "def", "function", "func", "module", "namespace", "record", "object",
+];
+
+/// The declaration lines of `content` (functions, types, classes, ...), keeping their indentation.
+///
+/// This is a heuristic: a line counts as a declaration when, after modifiers such as `pub` or `export`,
+/// it starts with a keyword like `fn`, `class` or `def`. Bodies, comments and statements are dropped.
+pub(crate) fn signatures(content: &str) -> String {
+    let mut outline = String::new();
+    for line in content.lines().filter(|line| is_declaration(line)) {
+        outline.push_str(line);
+        outline.push('\n');
+    }
+    outline
+}
+
+fn is_declaration(line: &str) -> bool {
+    declaration_keyword(line).is_some()
+}
+
+/// The keyword of a declaration line, e.g. `fn` for `pub async fn run()`, or `None` for other lines
+pub(crate) fn declaration_keyword(line: &str) -> Option<&str> {
+    let mut words = line.split_whitespace().peekable();
+    while words.next_if(|word| MODIFIERS.contains(word)).is_some() {}
+
+    let word = words.next()?;
+    // The keyword may run into generics or a name, as in `impl<T>` or `macro_rules! name`
+    let keyword = word.split(['<', '(', ':']).next().unwrap_or(word);
+    (DECLARATION_KEYWORDS.contains(&keyword) && (keyword != word || words.peek().is_some() || word.ends_with('!')))
+        .then_some(keyword)
+}
@@ -0,0 +1,54 @@
+//! Near-duplicate detection with MinHash signatures over word shingles
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+/// Number of hash functions in a signature; the similarity estimate is accurate to about ±1/sqrt of this
+const SIGNATURE_LENGTH: usize = 128;
+
+/// Words per shingle; longer shingles make the estimate more sensitive to reordering
+const SHINGLE_WORDS: usize = 3;
+
+/// A MinHash signature: for each hash function, the smallest hash over the content's shingles
+pub(crate) struct Signature([u64; SIGNATURE_LENGTH]);
+
+impl Signature {
+    /// The signature of `content`, or `None` when it has no words to compare
+    pub(crate) fn of(content: &str) -> Option<Signature> {
+        let words: Vec<&str> = content.split(|c: char| !c.is_alphanumeric() && c != '_').filter(|word| !word.is_empty()).collect();
+        if words.is_empty() {
+            return None;
+        }
+
+        let shingles: HashSet<u64> = words
+            .windows(SHINGLE_WORDS.min(words.len()))
+            .map(|shingle| {
+                let mut hasher = DefaultHasher::new();
+                shingle.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+
+        let mut minimums = [u64::MAX; SIGNATURE_LENGTH];
+        for shingle in shingles {
+            for (seed, minimum) in minimums.iter_mut().enumerate() {
+                *minimum = (*minimum).min(mix(shingle ^ (seed as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+            }
+        }
+        Some(Signature(minimums))
+    }
+
+    /// Estimated Jaccard similarity of the two contents' shingle sets (0.0 to 1.0)
+    pub(crate) fn similarity(&self, other: &Signature) -> f64 {
+        let equal = self.0.iter().zip(&other.0).filter(|(a, b)| a == b).count();
+        equal as f64 / SIGNATURE_LENGTH as f64
+    }
+}
+
+/// The splitmix64 finalizer, which turns the seeded shingle hash into an independent-looking hash
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
@@ -0,0 +1,98 @@
+//! One-line descriptions of files for `--file-summaries`, e.g. `12 fns, 3 structs`
+
+use crate::language::language_name;
+use crate::signatures::declaration_keyword;
+
+/// Languages described by their format rather than their contents
+const CONFIG_LANGUAGES: &[&str] = &["JSON", "TOML", "YAML", "XML"];
+
+/// Declarations counted in code: the label, its plural and the keywords counted under it
+const COUNTED_DECLARATIONS: &[(&str, &str, &[&str])] = &[
+    ("fn", "fns", &["fn", "function", "func", "def"]),
+    ("struct", "structs", &["struct", "record"]),
+    ("class", "classes", &["class", "object"]),
+    ("enum", "enums", &["enum"]),
+    ("trait", "traits", &["trait"]),
+    ("interface", "interfaces", &["interface"]),
+    ("impl", "impls", &["impl"]),
+    ("type", "types", &["type"]),
+];
+
+/// Longest heading quoted in a markdown summary, in characters
+const MAX_HEADING_CHARS: usize = 60;
+
+/// A cheap description of a file: the declarations in code (`12 fns, 3 structs`), the top heading of
+/// markdown (`# Installation`), the format of configuration (`TOML config`), and otherwise the line
+/// count (`42 lines`)
+pub(crate) fn file_summary(path: &str, content: &str) -> String {
+    let language = language_name(path);
+    if CONFIG_LANGUAGES.contains(&language) {
+        return format!("{} config", language);
+    }
+
+    if language == "Markdown" {
+        if let Some(heading) = top_heading(content) {
+            return format!("# {}", heading);
+        }
+    } else {
+        let mut counts = vec![0; COUNTED_DECLARATIONS.len()];
+        for keyword in content.lines().filter_map(declaration_keyword) {
+            if let Some(index) = COUNTED_DECLARATIONS.iter().position(|(_, _, keywords)| keywords.contains(&keyword)) {
+                counts[index] += 1;
+            }
+        }
+        let described: Vec<String> = COUNTED_DECLARATIONS
+            .iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|((label, plural, _), count)| format!("{} {}", count, if count == 1 { label } else { plural }))
+            .collect();
+        if !described.is_empty() {
+            return described.join(", ");
+        }
+    }
+
+    match content.lines().count() {
+        1 => String::from("1 line"),
+        lines => format!("{} lines", lines),
+    }
+}
+
+/// Whether a header label is a summary written by [`file_summary`], so parsers can tell it from a path
+pub(crate) fn is_summary_label(label: &str) -> bool {
+    let is_count = |part: &str| {
+        part.split_once(' ').is_some_and(|(count, noun)| {
+            !count.is_empty() && count.chars().all(|c| c.is_ascii_digit()) && !noun.is_empty() && noun.chars().all(|c| c.is_ascii_lowercase())
+        })
+    };
+    label.starts_with("# ") || label.strip_suffix(" config").is_some_and(|format| CONFIG_LANGUAGES.contains(&format)) || label.split(", ").all(is_count)
+}
+
+/// The first heading of the highest level outside code blocks, with brackets replaced so the label stays
+/// parseable, shortened to [`MAX_HEADING_CHARS`]
+fn top_heading(content: &str) -> Option<String> {
+    let mut in_code_block = false;
+    let mut best: Option<(usize, &str)> = None;
+    for line in content.lines() {
+        let line = line.trim_start();
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            continue;
+        }
+        let level = line.chars().take_while(|c| *c == '#').count();
+        let text = line[level..].trim();
+        let is_heading = (1..=6).contains(&level) && line[level..].starts_with(' ') && !text.is_empty();
+        if is_heading && !matches!(best, Some((best_level, _)) if best_level <= level) {
+            best = Some((level, text));
+        }
+    }
+
+    let heading: String = best?.1.replace('[', "(").replace(']', ")");
+    if heading.chars().count() <= MAX_HEADING_CHARS {
+        return Some(heading);
+    }
+    Some(format!("{}...", heading.chars().take(MAX_HEADING_CHARS).collect::<String>()))
+}
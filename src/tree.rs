@@ -0,0 +1,198 @@
+//! Rendering of the project structure as an indented directory tree
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+/// A directory (or file, when it has no children) in the tree built from display paths
+#[derive(Default)]
+pub(crate) struct TreeNode {
+    /// Children in order of first appearance
+    pub(crate) children: Vec<(String, TreeNode)>,
+    /// Number of files omitted from this directory
+    pub(crate) omitted: usize,
+    /// Text shown after a file's name, e.g. why its content was skipped or where a symlink points
+    pub(crate) note: Option<String>,
+}
+
+impl TreeNode {
+    /// Build a tree from `/`-separated display paths, each with an optional note
+    pub(crate) fn from_paths<'a>(
+        paths: impl IntoIterator<Item = (&'a str, Option<String>)>,
+        omitted_per_dir: &BTreeMap<String, usize>,
+    ) -> TreeNode {
+        let mut root = TreeNode::default();
+        for (path, note) in paths {
+            let mut node = &mut root;
+            for component in path.split('/').filter(|c| !c.is_empty()) {
+                node = node.child_mut(component);
+            }
+            node.note = note;
+        }
+
+        for (dir, omitted) in omitted_per_dir {
+            let mut node = &mut root;
+            for component in dir.split('/').filter(|c| !c.is_empty()) {
+                node = node.child_mut(component);
+            }
+            node.omitted = *omitted;
+        }
+
+        root
+    }
+
+    fn child_mut(&mut self, name: &str) -> &mut TreeNode {
+        let index = match self.children.iter().position(|(child, _)| child == name) {
+            Some(index) => index,
+            None => {
+                self.children.push((name.to_string(), TreeNode::default()));
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[index].1
+    }
+
+    /// Merge each directory whose only entry is another directory into it, naming the result `a/b/`, so that
+    /// chains like `src/main/java/com/example/` take one line
+    pub(crate) fn compact(&mut self) {
+        for (name, child) in &mut self.children {
+            while child.omitted == 0 && child.children.len() == 1 && child.children[0].1.is_dir() {
+                let (only_name, only_child) = child.children.pop().expect("checked for one child");
+                name.push('/');
+                name.push_str(&only_name);
+                *child = only_child;
+            }
+            child.compact();
+        }
+    }
+
+    /// Whether this node is a directory (has children or omitted files)
+    pub(crate) fn is_dir(&self) -> bool {
+        !self.children.is_empty() || self.omitted > 0
+    }
+}
+
+/// Render display paths as a tree, one line per entry, with directories suffixed by `/`.
+/// A file's note is appended verbatim after its name.
+///
+/// With `max_entries`, the tree is kept to about that many lines below the root: directories are expanded
+/// level by level, shallow ones first, while their entries fit, and the others are collapsed into a
+/// `dir/ (N files)` line. If even the top level doesn't fit, its first entries are followed by a
+/// `(+ N more files)` line. With `compact`, chains of single-directory directories take one line.
+pub(crate) fn render_tree<'a>(
+    paths: impl IntoIterator<Item = (&'a str, Option<String>)>,
+    omitted_per_dir: &BTreeMap<String, usize>,
+    max_entries: Option<usize>,
+    compact: bool,
+) -> Vec<String> {
+    let mut root = TreeNode::from_paths(paths, omitted_per_dir);
+    if compact {
+        root.compact();
+    }
+    let mut lines = vec![String::from(".")];
+    let listing = match max_entries {
+        Some(max_entries) => Listing {
+            expanded: Some(expanded_dirs(&root, max_entries)),
+            top_level: max_entries.min(root.children.len()),
+        },
+        None => Listing { expanded: None, top_level: root.children.len() },
+    };
+    render_children(&root, "", "", &listing, &mut lines);
+    lines
+}
+
+/// Which parts of a tree are shown
+struct Listing {
+    /// Paths of the directories whose entries are listed, or `None` for all of them
+    expanded: Option<HashSet<String>>,
+    /// Number of top-level entries listed
+    top_level: usize,
+}
+
+/// Lines a directory's own listing takes: its entries and the note on omitted files
+fn listing_lines(node: &TreeNode) -> usize {
+    node.children.len() + usize::from(node.omitted > 0)
+}
+
+/// Files in a subtree, including omitted ones
+fn file_count(node: &TreeNode) -> usize {
+    if !node.is_dir() {
+        return 1;
+    }
+    node.children.iter().map(|(_, child)| file_count(child)).sum::<usize>() + node.omitted
+}
+
+/// The directories to expand so the tree takes at most `max_entries` lines below the root, shallow first
+fn expanded_dirs(root: &TreeNode, max_entries: usize) -> HashSet<String> {
+    let mut expanded = HashSet::new();
+    let Some(mut remaining) = max_entries.checked_sub(listing_lines(root)) else {
+        return expanded;
+    };
+
+    let mut queue: VecDeque<(String, &TreeNode)> =
+        root.children.iter().filter(|(_, child)| child.is_dir()).map(|(name, child)| (name.clone(), child)).collect();
+    while let Some((path, node)) = queue.pop_front() {
+        let lines = listing_lines(node);
+        if lines > remaining {
+            continue;
+        }
+        remaining -= lines;
+        for (name, child) in node.children.iter().filter(|(_, child)| child.is_dir()) {
+            queue.push_back((format!("{}/{}", path, name), child));
+        }
+        expanded.insert(path);
+    }
+    expanded
+}
+
+fn render_children(node: &TreeNode, path: &str, prefix: &str, listing: &Listing, lines: &mut Vec<String>) {
+    let shown = if path.is_empty() { listing.top_level } else { node.children.len() };
+    let hidden: usize = node.children[shown..].iter().map(|(_, child)| file_count(child)).sum();
+    let count = shown + usize::from(node.omitted > 0) + usize::from(hidden > 0);
+
+    for (index, (name, child)) in node.children[..shown].iter().enumerate() {
+        let is_last = index + 1 == count;
+        let (branch, continuation) = if is_last { ("└── ", "    ") } else { ("├── ", "│   ") };
+        let child_path = if path.is_empty() { name.clone() } else { format!("{}/{}", path, name) };
+
+        if !child.is_dir() {
+            match &child.note {
+                Some(note) => lines.push(format!("{}{}{}{}", prefix, branch, name, note)),
+                None => lines.push(format!("{}{}{}", prefix, branch, name)),
+            }
+        } else if listing.expanded.as_ref().is_none_or(|expanded| expanded.contains(&child_path)) {
+            lines.push(format!("{}{}{}/", prefix, branch, name));
+            render_children(child, &child_path, &format!("{}{}", prefix, continuation), listing, lines);
+        } else {
+            let files = file_count(child);
+            lines.push(format!("{}{}{}/ ({} file{})", prefix, branch, name, files, if files == 1 { "" } else { "s" }));
+        }
+    }
+
+    if node.omitted > 0 {
+        let branch = if hidden > 0 { "├── " } else { "└── " };
+        lines.push(format!("{}{}({} more files in this directory omitted)", prefix, branch, node.omitted));
+    }
+    if hidden > 0 {
+        lines.push(format!("{}└── (+ {} more files)", prefix, hidden));
+    }
+}
+
+/// Render paths as a plain indented listing (two spaces per level, directories suffixed by `/`),
+/// the layout used by repomix's "Directory Structure" section
+pub(crate) fn render_indented<'a>(paths: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let root = TreeNode::from_paths(paths.into_iter().map(|path| (path, None)), &BTreeMap::new());
+    let mut lines = Vec::new();
+    render_indented_children(&root, 0, &mut lines);
+    lines
+}
+
+fn render_indented_children(node: &TreeNode, depth: usize, lines: &mut Vec<String>) {
+    for (name, child) in &node.children {
+        let indent = "  ".repeat(depth);
+        if child.is_dir() {
+            lines.push(format!("{}{}/", indent, name));
+            render_indented_children(child, depth + 1, lines);
+        } else {
+            lines.push(format!("{}{}", indent, name));
+        }
+    }
+}
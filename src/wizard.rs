@@ -0,0 +1,169 @@
+//! `contextify wizard`: a few questions about a project, answered with a `.blacklist`, a `.whitelist` and a
+//! `contextify.toml` in the current directory
+
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use contextify::config::{Config, ConfigSettings, CONFIG_FILE_NAME};
+use contextify::{detect_language_preset, language_presets, LanguagePreset, OutputFormat};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, Input, MultiSelect, Select};
+
+use crate::{DEFAULT_BLACKLIST, DEFAULT_WHITELIST};
+
+/// Tests and test data, for projects whose context should show the implementation only
+const TEST_PATTERNS: &[&str] = &["tests/", "test/", "__tests__/", "*_test.*", "*.test.*", "*.spec.*", "test_*.py"];
+
+/// Documentation, for projects whose context should show code only
+const DOC_PATTERNS: &[&str] = &["docs/", "*.md", "*.rst"];
+
+/// Documentation and configuration kept next to a language's sources with the narrower whitelist
+const DOC_AND_CONFIG_PATTERNS: &[&str] = &["*.md", "*.toml", "*.json", "*.yaml", "*.yml"];
+
+/// Ask about the project in the current directory and write its pattern files and config. Returns
+/// whether the user wants to run contextify with them right away.
+pub(crate) fn run_wizard() -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("The wizard asks questions and needs an interactive terminal");
+    }
+    let theme = ColorfulTheme::default();
+    println!("This sets up contextify for the project in the current directory. Press Enter to accept a default.\n");
+
+    // The language decides the build outputs to exclude and the sources to include
+    let presets: Vec<LanguagePreset> = language_presets().collect();
+    let detected = detect_language_preset(Path::new("."), &[PathBuf::from(".")]);
+    let mut languages: Vec<&str> = presets.iter().map(|preset| preset.language).collect();
+    languages.push("Other");
+    let default_language = detected
+        .and_then(|detected| presets.iter().position(|preset| *preset == detected))
+        .unwrap_or(presets.len());
+    let chosen = Select::with_theme(&theme)
+        .with_prompt("Main language of the project")
+        .items(&languages)
+        .default(default_language)
+        .interact()?;
+    let preset = presets.get(chosen);
+
+    let mut blacklist = String::from("# Files left out of the context, one pattern per line (written by `contextify wizard`)\n");
+    // Each choice with its section of the blacklist and whether it is checked at first
+    let mut exclusions: Vec<(String, String, bool)> = Vec::new();
+    if let Some(preset) = preset {
+        exclusions.push((
+            format!("Build outputs and dependencies of {} ({})", preset.language, preset.patterns.join(", ")),
+            format!("# Build outputs and dependencies of {}\n{}\n", preset.language, preset.patterns.join("\n")),
+            true,
+        ));
+    }
+    exclusions.push((
+        String::from("Common clutter: OS files, Docker files, lock files, caches (the defaults of `contextify init`)"),
+        format!("# Common clutter, as in the defaults of `contextify init`\n{}", DEFAULT_BLACKLIST),
+        true,
+    ));
+    exclusions.push((String::from("Tests"), format!("# Tests\n{}\n", TEST_PATTERNS.join("\n")), false));
+    exclusions.push((String::from("Documentation"), format!("# Documentation\n{}\n", DOC_PATTERNS.join("\n")), false));
+    let labels: Vec<&str> = exclusions.iter().map(|(label, _, _)| label.as_str()).collect();
+    let checked: Vec<bool> = exclusions.iter().map(|(_, _, checked)| *checked).collect();
+    let excluded = MultiSelect::with_theme(&theme)
+        .with_prompt("What to leave out (Space to toggle, Enter to confirm)")
+        .items(&labels)
+        .defaults(&checked)
+        .interact()?;
+    for index in excluded {
+        blacklist.push('\n');
+        blacklist.push_str(&exclusions[index].1);
+    }
+
+    let whitelist = match preset {
+        Some(preset) => {
+            let sources = preset.source_patterns();
+            let choices = [
+                String::from("Every file that isn't left out"),
+                format!("Only {} sources ({})", preset.language, sources.join(", ")),
+                format!("{} sources plus docs and config ({})", preset.language, DOC_AND_CONFIG_PATTERNS.join(", ")),
+            ];
+            let included = Select::with_theme(&theme)
+                .with_prompt("What to include")
+                .items(&choices)
+                .default(0)
+                .interact()?;
+            let mut patterns = sources;
+            if included == 2 {
+                patterns.extend(DOC_AND_CONFIG_PATTERNS.iter().map(|pattern| pattern.to_string()));
+            }
+            (included > 0).then(|| {
+                format!(
+                    "# Only files matching one of these patterns are included (written by `contextify wizard`)\n\n# {} sources{}\n{}\n",
+                    preset.language,
+                    if included == 2 { ", docs and config" } else { "" },
+                    patterns.join("\n")
+                )
+            })
+        }
+        None => {
+            let restrict = Confirm::with_theme(&theme)
+                .with_prompt("Include only Rust, Markdown and TOML files (the defaults of `contextify init`)?")
+                .default(false)
+                .interact()?;
+            restrict.then(|| {
+                format!("# Only files matching one of these patterns are included (written by `contextify wizard`)\n\n{}", DEFAULT_WHITELIST)
+            })
+        }
+    };
+
+    let formats = [("Plain text", OutputFormat::Text, "txt"), ("Markdown", OutputFormat::Markdown, "md"), ("JSON", OutputFormat::Json, "json")];
+    let labels: Vec<&str> = formats.iter().map(|(label, _, _)| *label).collect();
+    let chosen = Select::with_theme(&theme)
+        .with_prompt("Output format")
+        .items(&labels)
+        .default(0)
+        .interact()?;
+    let (_, format, extension) = formats[chosen];
+    let output: String = Input::with_theme(&theme)
+        .with_prompt("Output file")
+        .default(format!("project_contents.{}", extension))
+        .interact_text()?;
+
+    let settings = ConfigSettings {
+        blacklist_file: Some(String::from(".blacklist")),
+        whitelist_file: whitelist.is_some().then(|| String::from(".whitelist")),
+        output: Some(output),
+        format: Some(format),
+        ..Default::default()
+    };
+    let config = format!(
+        "# Settings for running contextify in this directory (written by `contextify wizard`).\n\
+         # Command-line options override them; the patterns are in the files named here.\n\n{}",
+        Config { settings, ..Default::default() }.to_toml()?
+    );
+
+    println!();
+    write_unless_declined(&theme, Path::new(".blacklist"), &blacklist)?;
+    if let Some(whitelist) = &whitelist {
+        write_unless_declined(&theme, Path::new(".whitelist"), whitelist)?;
+    }
+    write_unless_declined(&theme, Path::new(CONFIG_FILE_NAME), &config)?;
+
+    Ok(Confirm::with_theme(&theme)
+        .with_prompt("Run contextify with these settings now?")
+        .default(true)
+        .interact()?)
+}
+
+/// Write a file, asking first whether to overwrite it if it exists
+fn write_unless_declined(theme: &ColorfulTheme, path: &Path, content: &str) -> Result<()> {
+    if path.exists() {
+        let overwrite = Confirm::with_theme(theme)
+            .with_prompt(format!("{} exists; overwrite it?", path.display()))
+            .default(false)
+            .interact()?;
+        if !overwrite {
+            println!("Kept {}", path.display());
+            return Ok(());
+        }
+    }
+    fs::write(path, content).context(format!("Failed to write {}", path.display()))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
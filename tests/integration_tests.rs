@@ -296,4 +296,350 @@ fn test_custom_patterns_only() -> io::Result<()> {
     
     cleanup_test_directory(&test_dir)?;
     Ok(())
-} 
\ No newline at end of file
+} 
+
+/// Test writing one output file per top-level directory
+#[test]
+fn test_split_by_dir() -> io::Result<()> {
+    let test_dir = get_test_dir("split_by_dir_test");
+    setup_test_directory(&test_dir)?;
+    
+    let binary = get_binary_path();
+    
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .arg("--split-by-dir")
+        .arg("--output")
+        .arg("context.txt")
+        .output()?;
+    
+    assert!(output.status.success());
+    assert!(!test_dir.join("context.txt").exists());
+    
+    let root_content = fs::read_to_string(test_dir.join("context-root.txt"))?;
+    check_output_content(&root_content, &["file1.rs", "file2.md"], &["subfile1.rs"]);
+    
+    let subdir_content = fs::read_to_string(test_dir.join("context-subdir.txt"))?;
+    check_output_content(&subdir_content, &["subdir/subfile1.rs"], &["file2.md"]);
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test reporting the token estimate against a context window
+#[test]
+fn test_token_window_stats() -> io::Result<()> {
+    let test_dir = get_test_dir("token_window_test");
+    setup_test_directory(&test_dir)?;
+    
+    let binary = get_binary_path();
+    
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .arg("--stats")
+        .arg("--token-units")
+        .arg("separated")
+        .arg("--token-window")
+        .arg("10")
+        .arg("--output")
+        .arg("context.txt")
+        .output()?;
+    
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Context window: "));
+    assert!(stdout.contains("% of the window — will not fit"));
+    
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .arg("--token-window")
+        .arg("no-such-model")
+        .output()?;
+    
+    assert!(!output.status.success());
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test that global config files are looked up under $XDG_CONFIG_HOME
+#[test]
+fn test_xdg_config_locations() -> io::Result<()> {
+    let test_dir = get_test_dir("xdg_config_test");
+    setup_test_directory(&test_dir)?;
+    let config_home = test_dir.join("xdg");
+    
+    let binary = get_binary_path();
+    
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .env("XDG_CONFIG_HOME", &config_home)
+        .arg("init")
+        .output()?;
+    assert!(output.status.success());
+    assert!(config_home.join("contextify").join("blacklist").exists());
+    assert!(config_home.join("contextify").join("whitelist").exists());
+    
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .env("XDG_CONFIG_HOME", &config_home)
+        .arg("show-locations")
+        .output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&config_home.join("contextify").join("blacklist").display().to_string()));
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test failing when the filters leave too few files
+#[test]
+fn test_require_matches() -> io::Result<()> {
+    let test_dir = get_test_dir("require_matches_test");
+    setup_test_directory(&test_dir)?;
+    
+    let binary = get_binary_path();
+    
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .arg("--whitelist-patterns")
+        .arg("*.rsx")
+        .arg("--require-matches")
+        .arg("--output")
+        .arg("context.txt")
+        .output()?;
+    
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Only 0 files matched after filtering"));
+    
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .arg("--min-matches")
+        .arg("1")
+        .arg("--output")
+        .arg("context.txt")
+        .output()?;
+    
+    assert!(output.status.success());
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test keeping the previous output as a backup
+#[test]
+fn test_keep_previous() -> io::Result<()> {
+    let test_dir = get_test_dir("keep_previous_test");
+    setup_test_directory(&test_dir)?;
+    fs::write(test_dir.join("context.txt"), "previous context")?;
+    
+    let binary = get_binary_path();
+    
+    for _ in 0..2 {
+        let output = Command::new(&binary)
+            .current_dir(&test_dir)
+            .arg("--keep-previous")
+            .arg("--output")
+            .arg("context.txt")
+            .output()?;
+        assert!(output.status.success());
+    }
+    
+    let content = fs::read_to_string(test_dir.join("context.txt"))?;
+    check_output_content(&content, &["file1.rs"], &["context.txt.bak"]);
+    let backup = fs::read_to_string(test_dir.join("context.txt.bak"))?;
+    assert!(backup.contains("file1.rs"));
+    assert!(!test_dir.join("context.txt.tmp").exists());
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test keeping the order of a --files-from manifest
+#[test]
+fn test_files_from_preserve_order() -> io::Result<()> {
+    let test_dir = get_test_dir("files_from_test");
+    setup_test_directory(&test_dir)?;
+    fs::write(test_dir.join("order.list"), "# reading order\nsubdir/subfile1.rs\nfile2.md\nfile4.json\nfile1.rs\n")?;
+    
+    let binary = get_binary_path();
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .arg("--files-from")
+        .arg("order.list")
+        .arg("--preserve-order")
+        .arg("--output")
+        .arg("context.txt")
+        .output()?;
+    assert!(output.status.success());
+    
+    let content = fs::read_to_string(test_dir.join("context.txt"))?;
+    check_output_content(&content, &["subdir/subfile1.rs", "file2.md", "file1.rs"], &["file4.json", "file3.txt"]);
+    let positions: Vec<usize> = ["\nsubdir/subfile1.rs:", "\nfile2.md:", "\nfile1.rs:"]
+        .iter()
+        .map(|header| content.find(header).unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test creating the missing parent directories of the output file
+#[test]
+fn test_output_in_new_directory() -> io::Result<()> {
+    let test_dir = get_test_dir("nested_output_test");
+    setup_test_directory(&test_dir)?;
+    
+    let binary = get_binary_path();
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .arg("--output")
+        .arg("nested/dir/out.txt")
+        .output()?;
+    assert!(output.status.success());
+    
+    let content = fs::read_to_string(test_dir.join("nested/dir/out.txt"))?;
+    check_output_content(&content, &["file1.rs"], &[]);
+    
+    // A parent that can't be created is reported by name
+    fs::write(test_dir.join("blocker"), "a file, not a directory")?;
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .arg("--output")
+        .arg("blocker/out.txt")
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to create output directory: blocker"));
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test running from a working directory that was deleted, with absolute input and output paths
+#[cfg(unix)]
+#[test]
+fn test_deleted_working_directory() -> io::Result<()> {
+    let test_dir = get_test_dir("deleted_cwd_test");
+    setup_test_directory(&test_dir)?;
+    let deleted_dir = get_test_dir("deleted_cwd_test_cwd");
+    fs::create_dir_all(&deleted_dir)?;
+    let output_file = test_dir.with_file_name("deleted_cwd_test_out.txt");
+    
+    // The shell removes its own working directory before starting contextify in it
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg("cd \"$1\" && rmdir \"$1\" && exec \"$2\" --input-paths \"$3\" --output \"$4\"")
+        .arg("sh")
+        .arg(&deleted_dir)
+        .arg(get_binary_path())
+        .arg(&test_dir)
+        .arg(&output_file)
+        .output()?;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!deleted_dir.exists());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("showing paths relative to"));
+    
+    let content = fs::read_to_string(&output_file)?;
+    check_output_content(&content, &["file1.rs", "subdir/subfile1.rs"], &[]);
+    
+    fs::remove_file(&output_file)?;
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test the default output file in a git repository, which is only used when standard output is a terminal
+#[cfg(target_os = "linux")]
+#[test]
+fn test_default_output_in_git_repository() -> io::Result<()> {
+    let test_dir = get_test_dir("default_output_test");
+    setup_test_directory(&test_dir)?;
+    assert!(Command::new("git").arg("init").arg("-q").current_dir(&test_dir).status()?.success());
+    
+    // `script` gives contextify a terminal as standard output
+    let binary = get_binary_path();
+    let run_in_terminal = |args: &str| -> io::Result<String> {
+        let output = Command::new("script")
+            .arg("-qec")
+            .arg(format!("'{}' {}", binary.display(), args))
+            .arg("/dev/null")
+            .current_dir(&test_dir)
+            .output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let default_output = test_dir.join(".contextify/context.txt");
+    let gitignore = test_dir.join(".gitignore");
+    let original_gitignore = fs::read_to_string(&gitignore)?;
+    
+    run_in_terminal("")?;
+    check_output_content(&fs::read_to_string(&default_output)?, &["file1.rs"], &["context.txt:"]);
+    assert_eq!(fs::read_to_string(&gitignore)?, format!("{}.contextify/\n", original_gitignore));
+    
+    fs::write(&gitignore, &original_gitignore)?;
+    fs::remove_dir_all(test_dir.join(".contextify"))?;
+    run_in_terminal("--no-gitignore-update")?;
+    assert!(default_output.exists());
+    assert_eq!(fs::read_to_string(&gitignore)?, original_gitignore);
+    
+    // Runs that only print leave the output file and .gitignore alone
+    fs::remove_dir_all(test_dir.join(".contextify"))?;
+    run_in_terminal("--dry-run")?;
+    run_in_terminal("--estimate-only")?;
+    let tree = run_in_terminal("tree")?;
+    assert!(tree.contains("file1.rs"));
+    assert!(!test_dir.join(".contextify").exists());
+    assert_eq!(fs::read_to_string(&gitignore)?, original_gitignore);
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test that a context written to standard output holds nothing but the context
+#[test]
+fn test_stdout_holds_only_the_context() -> io::Result<()> {
+    let test_dir = get_test_dir("stdout_only_test");
+    setup_test_directory(&test_dir)?;
+    
+    let output = Command::new(get_binary_path())
+        .current_dir(&test_dir)
+        .arg("--format")
+        .arg("json")
+        .arg("--stats")
+        .output()?;
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).expect("stdout is a single JSON document");
+    assert!(json["files"].as_array().is_some_and(|files| !files.is_empty()));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("STATISTICS:"));
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}
+
+/// Test that dump-config writes the options it has settings for and refuses the others
+#[test]
+fn test_dump_config() -> io::Result<()> {
+    let test_dir = get_test_dir("dump_config_test");
+    setup_test_directory(&test_dir)?;
+    
+    let binary = get_binary_path();
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .args(["--whitelist-patterns", "*.rs", "--output", "review.txt", "dump-config"])
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "whitelist_patterns = [\"*.rs\"]\noutput = \"review.txt\"\n");
+    
+    let output = Command::new(&binary)
+        .current_dir(&test_dir)
+        .args(["--whitelist-patterns", "*.rs", "--max-tokens", "1000", "--sort", "natural", "dump-config", "contextify.toml"])
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--max-tokens, --sort can't be written to contextify.toml"));
+    assert!(!test_dir.join("contextify.toml").exists());
+    
+    cleanup_test_directory(&test_dir)?;
+    Ok(())
+}